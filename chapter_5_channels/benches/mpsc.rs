@@ -0,0 +1,92 @@
+// Compares the lock-free mpsc channel with naive_channel and std::sync::mpsc.
+// Run with `cargo bench --bench mpsc`.
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chapter_5_channels::mpsc;
+use chapter_5_channels::naive_channel::Channel as NaiveChannel;
+
+const MESSAGES_PER_PRODUCER: usize = 200_000;
+const RUNS: u32 = 5;
+
+fn main() {
+    for producers in [1, 4, 8] {
+        println!("{producers} producer(s), {MESSAGES_PER_PRODUCER} messages each:");
+        report("naive_channel", bench(producers, naive_channel));
+        report("std::sync::mpsc", bench(producers, std_mpsc));
+        report("mpsc", bench(producers, lock_free_mpsc));
+        println!();
+    }
+}
+
+fn bench(producers: usize, run: fn(usize) -> usize) -> Duration {
+    // warm up
+    run(producers);
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let received = run(producers);
+        assert_eq!(received, producers * MESSAGES_PER_PRODUCER);
+    }
+    start.elapsed() / RUNS
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!("  {name:<16} {elapsed:>12.2?}");
+}
+
+fn naive_channel(producers: usize) -> usize {
+    let channel = Arc::new(NaiveChannel::new());
+    thread::scope(|s| {
+        for _ in 0..producers {
+            let channel = channel.clone();
+            s.spawn(move || {
+                for i in 0..MESSAGES_PER_PRODUCER {
+                    channel.send(i).unwrap();
+                }
+            });
+        }
+        // closing would need the senders to agree on who's last, so count instead
+        let count = producers * MESSAGES_PER_PRODUCER;
+        for _ in 0..count {
+            channel.receive();
+        }
+        count
+    })
+}
+
+fn std_mpsc(producers: usize) -> usize {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..producers {
+            let sender = sender.clone();
+            s.spawn(move || {
+                for i in 0..MESSAGES_PER_PRODUCER {
+                    sender.send(i).unwrap();
+                }
+            });
+        }
+        drop(sender);
+        receiver.iter().count()
+    })
+}
+
+fn lock_free_mpsc(producers: usize) -> usize {
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..producers {
+            let sender = sender.clone();
+            s.spawn(move || {
+                for i in 0..MESSAGES_PER_PRODUCER {
+                    sender.send(i).unwrap();
+                }
+            });
+        }
+        drop(sender);
+        let mut count = 0;
+        while receiver.receive().is_ok() {
+            count += 1;
+        }
+        count
+    })
+}
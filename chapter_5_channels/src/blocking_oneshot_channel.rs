@@ -1,7 +1,8 @@
 use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::thread::{self, Thread};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool};
+
+use crate::sync::{const_fn, thread, AtomicBool, LoadMut, Thread, UnsafeCell};
 
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
@@ -21,10 +22,12 @@ pub struct Receiver<'a, T> {
 }
 
 impl<T> Channel<T> {
-    pub const fn new() -> Self {
-        Self {
-            message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                ready: AtomicBool::new(false),
+            }
         }
     }
 
@@ -33,6 +36,7 @@ impl<T> Channel<T> {
     //     *self = Self::new();
     //     (Sender { channel: self }, Receiver { channel: self })
     // }
+    #[allow(mismatched_lifetime_syntaxes)] // the book's signature, which predates this lint
     pub fn split(&mut self) -> (Sender<T>, Receiver<T>) {
         *self = Self::new();
         (
@@ -50,7 +54,9 @@ impl<T> Channel<T> {
 
 impl<T> Sender<'_, T> {
     pub fn send(self, message: T) {
-        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel
+            .message
+            .with_mut(|m| unsafe { (*m).write(message) });
         self.channel.ready.store(true, Release);
         self.receiving_thread.unpark();
     }
@@ -66,17 +72,27 @@ impl<T> Receiver<'_, T> {
         // (Or because something other than our send method called unpark().)
         // This means that we cannot assume that the ready flag has been set when park() returns.
         // So, we need to use a loop to check the flag again after getting unparked.
-        while !self.channel.ready.swap(false, Acquire) {
+        // (Unlike `swap(false)`, a failed compare_exchange doesn't store anything while waiting.
+        // Loom doesn't always order such a store before the sender's, and then reports a deadlock.)
+        while self
+            .channel
+            .ready
+            .compare_exchange(true, false, Acquire, Relaxed)
+            .is_err()
+        {
             thread::park();
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        self.channel
+            .message
+            .with(|m| unsafe { (*m).assume_init_read() })
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() }
+        if self.ready.load_mut() {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
         }
     }
 }
@@ -0,0 +1,379 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+
+// Every receiver keeps its own cursor (the position of the next message it'll read) into a shared ring buffer.
+// The sender never waits for slow receivers: it overwrites the oldest message,
+// and a receiver whose cursor fell behind the buffer is told how many messages it missed.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    next: u64,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    item_ready: Condvar,
+    wakers: Wakers,
+    // depth is the number of messages in the buffer, every receiver receiving a message counts
+    metrics: Metrics,
+}
+
+struct State<T> {
+    buffer: Box<[Option<T>]>, // message at position `p` lives at `p % capacity`
+    tail: u64,                // position of the next message to be sent
+    sender_count: usize,
+    receiver_count: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceiveError {
+    /// All senders are gone and every message has been received.
+    Closed,
+    /// The receiver fell behind and this many messages were overwritten before it could see them.
+    /// The next receive continues from the oldest message still available.
+    Lagged(u64),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryReceiveError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: (0..capacity).map(|_| None).collect(),
+            tail: 0,
+            sender_count: 1,
+            receiver_count: 1,
+        }),
+        item_ready: Condvar::new(),
+        wakers: Wakers::new(),
+        metrics: Metrics::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl<T: Clone> State<T> {
+    fn receive_at(&self, next: &mut u64) -> Result<T, TryReceiveError> {
+        let capacity = self.buffer.len() as u64;
+        let oldest = self.tail.saturating_sub(capacity);
+        if *next < oldest {
+            let missed = oldest - *next;
+            *next = oldest;
+            return Err(TryReceiveError::Lagged(missed));
+        }
+        if *next == self.tail {
+            return Err(if self.sender_count == 0 {
+                TryReceiveError::Closed
+            } else {
+                TryReceiveError::Empty
+            });
+        }
+        let index = (*next % capacity) as usize;
+        // positions in oldest..tail are always filled
+        let message = self.buffer[index].clone().unwrap();
+        *next += 1;
+        Ok(message)
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    /// Returns the number of receivers that will see the message,
+    /// or gives the message back if there are none.
+    pub fn send(&self, message: T) -> Result<usize, SendError<T>> {
+        let mut state = self.shared.lock();
+        if state.receiver_count == 0 {
+            return Err(SendError(message));
+        }
+        let capacity = state.buffer.len() as u64;
+        let index = (state.tail % capacity) as usize;
+        if state.buffer[index].replace(message).is_some() {
+            self.shared.metrics.discarded(1);
+        }
+        state.tail += 1;
+        self.shared.metrics.sent(1);
+        let receiver_count = state.receiver_count;
+        drop(state);
+        self.shared.item_ready.notify_all();
+        self.shared.wakers.wake_all();
+        Ok(receiver_count)
+    }
+
+    /// Sends all messages while holding the lock only once.
+    /// Returns the number of receivers that will see them,
+    /// or gives the messages back if there are none.
+    /// The iterator runs before locking: if it panics or uses the channel, the lock isn't held.
+    pub fn send_batch(
+        &self,
+        messages: impl IntoIterator<Item = T>,
+    ) -> Result<usize, SendError<Vec<T>>> {
+        let messages: Vec<T> = messages.into_iter().collect();
+        let mut state = self.shared.lock();
+        if state.receiver_count == 0 {
+            return Err(SendError(messages));
+        }
+        let capacity = state.buffer.len() as u64;
+        for message in messages {
+            let index = (state.tail % capacity) as usize;
+            if state.buffer[index].replace(message).is_some() {
+                self.shared.metrics.discarded(1);
+            }
+            state.tail += 1;
+            self.shared.metrics.sent(1);
+        }
+        let receiver_count = state.receiver_count;
+        drop(state);
+        self.shared.item_ready.notify_all();
+        self.shared.wakers.wake_all();
+        Ok(receiver_count)
+    }
+
+    /// The new receiver only sees messages sent after this call.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock();
+        state.receiver_count += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.tail,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock().receiver_count
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().sender_count += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_count -= 1;
+        if state.sender_count == 0 {
+            drop(state);
+            // wake up receivers waiting for a message that will never come
+            self.shared.item_ready.notify_all();
+            self.shared.wakers.wake_all();
+        }
+    }
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn try_receive(&mut self) -> Result<T, TryReceiveError> {
+        let state = self.shared.lock();
+        let message = state.receive_at(&mut self.next)?;
+        self.shared.metrics.delivered(1);
+        Ok(message)
+    }
+
+    /// Blocks until a message is available.
+    pub fn receive(&mut self) -> Result<T, ReceiveError> {
+        let timer = Timer::start();
+        let mut state = self.shared.lock();
+        let result = loop {
+            match state.receive_at(&mut self.next) {
+                Ok(message) => {
+                    self.shared.metrics.delivered(1);
+                    break Ok(message);
+                }
+                Err(TryReceiveError::Lagged(n)) => break Err(ReceiveError::Lagged(n)),
+                Err(TryReceiveError::Closed) => break Err(ReceiveError::Closed),
+                Err(TryReceiveError::Empty) => {
+                    state = self.shared.item_ready.wait(state).unwrap();
+                }
+            }
+        };
+        drop(state);
+        self.shared.metrics.receiver_waited(timer);
+        result
+    }
+
+    /// Number of messages this receiver hasn't seen yet (including the ones it lagged behind on).
+    pub fn len(&self) -> usize {
+        (self.shared.lock().tail - self.next) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        let state = self.shared.lock();
+        self.next != state.tail || state.sender_count == 0
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.shared.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.shared.wakers.unregister(waker);
+    }
+}
+
+// A clone continues from the same position as the original.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receiver_count += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_count -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn test_every_receiver_sees_every_message() {
+        let (sender, mut a) = channel(16);
+        let mut b = sender.subscribe();
+        assert_eq!(sender.receiver_count(), 2);
+
+        thread::scope(|s| {
+            let ta = s.spawn(move || (0..10).map(|_| a.receive().unwrap()).collect::<Vec<_>>());
+            let tb = s.spawn(move || (0..10).map(|_| b.receive().unwrap()).collect::<Vec<_>>());
+            for i in 0..10 {
+                assert_eq!(sender.send(i), Ok(2));
+            }
+            let expected: Vec<_> = (0..10).collect();
+            assert_eq!(ta.join().unwrap(), expected);
+            assert_eq!(tb.join().unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn test_subscribe_only_sees_later_messages() {
+        let (sender, _receiver) = channel(4);
+        sender.send(1).unwrap();
+        let mut late = sender.subscribe();
+        assert_eq!(late.try_receive(), Err(TryReceiveError::Empty));
+        sender.send(2).unwrap();
+        assert_eq!(late.try_receive(), Ok(2));
+    }
+
+    #[test]
+    fn test_slow_receiver_lags() {
+        let (sender, mut receiver) = channel(2);
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.len(), 5);
+        assert_eq!(receiver.receive(), Err(ReceiveError::Lagged(3)));
+        assert_eq!(receiver.receive(), Ok(3));
+        assert_eq!(receiver.receive(), Ok(4));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn test_send_batch() {
+        let (sender, mut receiver) = channel(4);
+        assert_eq!(sender.send_batch(0..3), Ok(1));
+        assert_eq!(sender.send_batch(3..6), Ok(1));
+        assert_eq!(receiver.receive(), Err(ReceiveError::Lagged(2)));
+        for i in 2..6 {
+            assert_eq!(receiver.receive(), Ok(i));
+        }
+        drop(receiver);
+        assert_eq!(sender.send_batch([7, 8]), Err(SendError(vec![7, 8])));
+    }
+
+    #[test]
+    fn test_send_batch_panicking_iterator() {
+        let (sender, mut receiver) = channel(4);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sender.send_batch((0..5).inspect(|&i| assert!(i < 3, "iterator panicked")))
+        }));
+        assert!(result.is_err());
+        // nothing was sent, and the lock isn't poisoned
+        assert_eq!(receiver.try_receive(), Err(TryReceiveError::Empty));
+        // the iterator can use the channel itself
+        assert_eq!(
+            sender.send_batch((0..2).map(|i| i + sender.receiver_count())),
+            Ok(1)
+        );
+        assert_eq!(receiver.receive(), Ok(1));
+        assert_eq!(receiver.receive(), Ok(2));
+    }
+
+    #[test]
+    fn test_closed_after_sender_dropped() {
+        let (sender, mut receiver) = channel(4);
+        let sender2 = sender.clone();
+        sender.send("a").unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Ok("a"));
+        assert_eq!(receiver.try_receive(), Err(TryReceiveError::Empty));
+        thread::scope(|s| {
+            let t = s.spawn(|| receiver.receive());
+            drop(sender2);
+            assert_eq!(t.join().unwrap(), Err(ReceiveError::Closed));
+        });
+    }
+
+    #[test]
+    fn test_send_without_receivers() {
+        let (sender, receiver) = channel(4);
+        let clone = receiver.clone();
+        drop(receiver);
+        assert_eq!(sender.send(1), Ok(1));
+        drop(clone);
+        assert_eq!(sender.receiver_count(), 0);
+        assert_eq!(sender.send(2), Err(SendError(2)));
+    }
+}
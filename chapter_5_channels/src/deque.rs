@@ -0,0 +1,355 @@
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr};
+use std::sync::{Arc, Mutex};
+
+// The Chase-Lev work-stealing deque ("Correct and Efficient Work-Stealing for Weak Memory Models",
+// Lê, Pop, Cohen and Zappa Nardelli), for task schedulers:
+// the owning worker pushes and pops at the bottom, like a stack,
+// other threads steal from the top, the oldest items.
+//
+// The items between `top` and `bottom` live in a circular buffer.
+// Only the worker moves `bottom`, stealers race for `top` with a compare-and-swap.
+// The only item both ends can want is the last one, in which case the worker joins the race for `top` too.
+//
+// A full buffer is replaced by one twice the size. Stealers might still be reading from the old one,
+// so it's only freed together with the deque, when the worker and all stealers are gone.
+// Since every buffer is twice the size of the previous one, they add up to less than the latest one.
+
+const MIN_CAPACITY: usize = 16;
+
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    _not_sync: PhantomData<Cell<()>>, // only one thread may push and pop at a time
+}
+
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    Success(T),
+    Empty,
+    /// Lost a race with another thread taking the same item, trying again might succeed.
+    Retry,
+}
+
+struct Inner<T> {
+    top: AtomicIsize,    // next item to steal
+    bottom: AtomicIsize, // next slot to push to
+    buffer: AtomicPtr<Buffer<T>>,
+    retired: Mutex<Vec<*mut Buffer<T>>>, // replaced buffers, stealers might still be reading them
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>, // power of two length
+}
+
+impl<T> Buffer<T> {
+    fn allocate(capacity: usize) -> *mut Buffer<T> {
+        debug_assert!(capacity.is_power_of_two());
+        Box::into_raw(Box::new(Buffer {
+            slots: (0..capacity)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+        }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    /// Safety: nobody may be reading the slot as an initialised value.
+    unsafe fn write(&self, index: isize, item: T) {
+        (*self.slot(index)).write(item);
+    }
+
+    /// A bitwise copy of the slot, which only becomes an item once the copy is known to be ours.
+    /// Until then, the worker might be overwriting it at the same time.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        ptr::read_volatile(self.slot(index))
+    }
+}
+
+pub fn deque<T>() -> (Worker<T>, Stealer<T>) {
+    let inner = Arc::new(Inner {
+        top: AtomicIsize::new(0),
+        bottom: AtomicIsize::new(0),
+        buffer: AtomicPtr::new(Buffer::allocate(MIN_CAPACITY)),
+        retired: Mutex::new(Vec::new()),
+    });
+    (
+        Worker {
+            inner: inner.clone(),
+            _not_sync: PhantomData,
+        },
+        Stealer { inner },
+    )
+}
+
+impl<T> Worker<T> {
+    pub fn push(&self, item: T) {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Relaxed);
+        // Acquire: the stealers must be done reading the slots they've taken before we reuse them.
+        let top = inner.top.load(Acquire);
+        // Only we replace the buffer.
+        let mut buffer = inner.buffer.load(Relaxed);
+        // Safety: buffers are only freed when the deque is dropped.
+        if bottom - top >= unsafe { (*buffer).capacity() } as isize {
+            buffer = self.grow(top, bottom);
+        }
+        // Safety: the slot at `bottom` isn't part of the deque, so nobody takes it.
+        unsafe { (*buffer).write(bottom, item) };
+        // Release: stealers that see the new bottom must see the item.
+        fence(Release);
+        inner.bottom.store(bottom + 1, Relaxed);
+    }
+
+    fn grow(&self, top: isize, bottom: isize) -> *mut Buffer<T> {
+        let inner = &*self.inner;
+        let old = inner.buffer.load(Relaxed);
+        // Safety: we're the only one replacing buffers, and old ones are never freed while we exist.
+        let new = unsafe {
+            let new = Buffer::allocate((*old).capacity() * 2);
+            for i in top..bottom {
+                ptr::copy_nonoverlapping((*old).slot(i), (*new).slot(i), 1);
+            }
+            new
+        };
+        // Release: stealers that see the new buffer must see the copied items.
+        inner.buffer.store(new, Release);
+        // The old buffer still holds (copies of) items, but it's never dropped as anything but raw slots.
+        inner.retired.lock().unwrap().push(old);
+        new
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Relaxed) - 1;
+        let buffer = inner.buffer.load(Relaxed);
+        // Claim the bottom item first, so new stealers don't go for it...
+        inner.bottom.store(bottom, Relaxed);
+        // ...and then see whether stealers that came before got to it.
+        // SeqCst pairs with the fence in Stealer::steal:
+        // either we see their new top, or they see our new bottom.
+        fence(SeqCst);
+        let top = inner.top.load(Relaxed);
+        if top > bottom {
+            // It was already empty.
+            inner.bottom.store(bottom + 1, Relaxed);
+            return None;
+        }
+        // Safety: the slot is in the deque, so it was written.
+        let item = unsafe { (*buffer).read(bottom) };
+        if top == bottom {
+            // The last item: stealers might still be going for it, so race them for it.
+            let won = inner
+                .top
+                .compare_exchange(top, top + 1, SeqCst, Relaxed)
+                .is_ok();
+            inner.bottom.store(bottom + 1, Relaxed);
+            if !won {
+                // Our copy belongs to the stealer now.
+                return None;
+            }
+        }
+        // Safety: the item is ours alone.
+        Some(unsafe { item.assume_init() })
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Takes the oldest item.
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let top = inner.top.load(Acquire);
+        // SeqCst pairs with the fence in Worker::pop.
+        fence(SeqCst);
+        // Acquire synchronises with the fence in Worker::push, making the items up to `bottom` visible.
+        let bottom = inner.bottom.load(Acquire);
+        if top >= bottom {
+            return Steal::Empty;
+        }
+        // Acquire: the items copied to a new buffer must be visible.
+        let buffer = inner.buffer.load(Acquire);
+        // Safety: buffers are only freed when the deque is dropped.
+        // Until we win the race for `top`, the copy is just bytes that might be overwritten any time.
+        let item = unsafe { (*buffer).read(top) };
+        if inner
+            .top
+            .compare_exchange(top, top + 1, SeqCst, Relaxed)
+            .is_err()
+        {
+            return Steal::Retry;
+        }
+        // Safety: we won the race, the item is ours alone.
+        Steal::Success(unsafe { item.assume_init() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    /// Only a snapshot: other threads might be changing it.
+    fn len(&self) -> usize {
+        let bottom = self.bottom.load(Relaxed);
+        let top = self.top.load(Relaxed);
+        (bottom - top).max(0) as usize
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+        let buffer = *self.buffer.get_mut();
+        // Safety: everyone's gone, the items between top and bottom were never taken.
+        unsafe {
+            for i in top..bottom {
+                (*(*buffer).slot(i)).assume_init_drop();
+            }
+            drop(Box::from_raw(buffer));
+            for old in self.retired.get_mut().unwrap().drain(..) {
+                drop(Box::from_raw(old));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn test_pop_lifo_steal_fifo() {
+        let (worker, stealer) = deque();
+        for i in 0..4 {
+            worker.push(i);
+        }
+        assert_eq!(worker.len(), 4);
+        assert_eq!(worker.pop(), Some(3));
+        assert_eq!(stealer.steal(), Steal::Success(0));
+        assert_eq!(stealer.clone().steal(), Steal::Success(1));
+        assert_eq!(worker.pop(), Some(2));
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+        assert!(stealer.is_empty());
+    }
+
+    #[test]
+    fn test_grows() {
+        let (worker, stealer) = deque();
+        for i in 0..1000 {
+            worker.push(i);
+            if i % 3 == 0 {
+                assert!(matches!(stealer.steal(), Steal::Success(_)));
+            }
+        }
+        assert_eq!(worker.len(), 666);
+        let mut items: Vec<_> = std::iter::from_fn(|| worker.pop()).collect();
+        items.reverse();
+        assert_eq!(items, (334..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_drop_items() {
+        let item = Arc::new(());
+        let (worker, stealer) = deque();
+        for _ in 0..100 {
+            worker.push(item.clone());
+        }
+        worker.pop();
+        drop(stealer.steal());
+        drop(worker);
+        assert_eq!(Arc::strong_count(&item), 99);
+        drop(stealer);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn test_stress_no_loss_or_duplication() {
+        const ITEMS: usize = 100_000;
+        const STEALERS: usize = 4;
+        let (worker, stealer) = deque();
+        let done = AtomicBool::new(false);
+        let (mut taken, stolen) = thread::scope(|s| {
+            let stealers: Vec<_> = (0..STEALERS)
+                .map(|_| {
+                    let stealer = stealer.clone();
+                    let done = &done;
+                    s.spawn(move || {
+                        let mut stolen = Vec::new();
+                        loop {
+                            match stealer.steal() {
+                                Steal::Success(item) => stolen.push(item),
+                                Steal::Retry => {}
+                                Steal::Empty if done.load(Acquire) => break,
+                                Steal::Empty => thread::yield_now(),
+                            }
+                        }
+                        stolen
+                    })
+                })
+                .collect();
+            let mut taken = Vec::new();
+            for i in 0..ITEMS {
+                worker.push(i);
+                // pop every now and then, sometimes the last item, to race the stealers for it
+                if i % 3 == 0 {
+                    taken.extend(worker.pop());
+                }
+            }
+            taken.extend(std::iter::from_fn(|| worker.pop()));
+            done.store(true, Release);
+            let stolen: Vec<Vec<_>> = stealers.into_iter().map(|t| t.join().unwrap()).collect();
+            (taken, stolen)
+        });
+        for s in stolen {
+            taken.extend(s);
+        }
+        taken.sort();
+        assert_eq!(taken, (0..ITEMS).collect::<Vec<_>>());
+    }
+}
@@ -1,6 +1,19 @@
 pub mod blocking_oneshot_channel;
+pub mod broadcast;
+pub mod deque;
 pub mod mem_opt_oneshot_channel;
+pub mod metrics;
+pub mod movable_blocking_oneshot_channel;
+pub mod mpsc;
 pub mod naive_channel;
 pub mod oneshot_channel;
+pub mod oneshot_slot;
+pub mod priority_channel;
+pub mod promise;
+pub mod rendezvous;
+pub mod select;
 pub mod send_recv_oneshot_channel;
 pub mod send_recv_oneshot_channel_noarc;
+mod sync;
+pub mod thread_pool;
+pub mod watch;
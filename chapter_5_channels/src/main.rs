@@ -3,8 +3,10 @@ use std::thread;
 use std::time::Duration;
 
 use chapter_5_channels::blocking_oneshot_channel::Channel as BloockingChannel;
+use chapter_5_channels::movable_blocking_oneshot_channel::Channel as MovableBlockingChannel;
 use chapter_5_channels::naive_channel::Channel as NaiveChannel;
 use chapter_5_channels::oneshot_channel::Channel as OneshotChannel;
+use chapter_5_channels::select;
 use chapter_5_channels::send_recv_oneshot_channel::channel;
 use chapter_5_channels::send_recv_oneshot_channel_noarc::Channel;
 
@@ -14,6 +16,48 @@ fn main() {
     use_sender_receiver();
     use_sender_receiver_split();
     use_blocking_channel();
+    use_movable_blocking_channel();
+    use_select();
+}
+
+fn use_select() {
+    // instead of polling is_ready() in a loop,
+    // block until either of the channels has something for us
+    let messages = NaiveChannel::new();
+    let (sender, receiver) = channel();
+    thread::scope(|s| {
+        s.spawn(|| {
+            messages.send("select: message").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            sender.send("select: done");
+        });
+        loop {
+            let done = select! {
+                messages => {
+                    println!("{}", messages.receive().unwrap());
+                    false
+                },
+                receiver => true,
+            };
+            if done {
+                println!("{}", receiver.receive());
+                break;
+            }
+        }
+    });
+}
+
+fn use_movable_blocking_channel() {
+    // the receiver registers its own thread when it starts waiting,
+    // so it can be handed to another thread
+    let mut channel = MovableBlockingChannel::new();
+    thread::scope(|s| {
+        let (sender, receiver) = channel.split();
+        s.spawn(move || {
+            println!("{}", receiver.receive());
+        });
+        sender.send("movable blocking channel");
+    });
 }
 
 fn use_blocking_channel() {
@@ -82,11 +126,13 @@ fn use_naive_channel() {
     let reader = channel.clone();
     thread::spawn(move || {
         for _ in 0..5 {
-            channel.send("naive channel");
+            channel.send("naive channel").unwrap();
             thread::sleep(Duration::from_secs(1));
         }
+        // lets the reader know there's nothing more to come
+        channel.close();
     });
-    for _ in 0..5 {
-        println!("{}", reader.receive());
+    for message in reader.iter() {
+        println!("{}", message);
     }
 }
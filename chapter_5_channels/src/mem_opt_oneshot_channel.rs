@@ -1,5 +1,7 @@
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicU8};
+
+use crate::sync::{const_fn, AtomicU8, LoadMut, UnsafeCell};
 
 const EMPTY: u8 = 0;
 const WRITING: u8 = 1;
@@ -15,10 +17,12 @@ pub struct Channel<T> {
 unsafe impl<T: Send> Sync for Channel<T> {}
 
 impl<T> Channel<T> {
-    pub const fn new() -> Self {
-        Self {
-            message: UnsafeCell::new(MaybeUninit::uninit()),
-            state: AtomicU8::new(EMPTY),
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicU8::new(EMPTY),
+            }
         }
     }
 
@@ -30,7 +34,7 @@ impl<T> Channel<T> {
         {
             panic!("can't send more than one message!");
         }
-        unsafe { (*self.message.get()).write(message) };
+        self.message.with_mut(|m| unsafe { (*m).write(message) });
         self.state.store(READY, Release);
     }
 
@@ -46,14 +50,15 @@ impl<T> Channel<T> {
         {
             panic!("no message available!");
         }
-        unsafe { (*self.message.get()).assume_init_read() }
+        self.message.with(|m| unsafe { (*m).assume_init_read() })
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.state.get_mut() == READY {
-            unsafe { self.message.get_mut().assume_init_drop() }
+        if self.state.load_mut() == READY {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
         }
     }
 }
@@ -0,0 +1,286 @@
+// Opt-in channel instrumentation, enabled with the `metrics` feature.
+//
+// Covers the channels that carry more than one message: naive_channel, mpsc, broadcast, watch,
+// rendezvous and priority_channel, which all have a `stats` method.
+// The oneshot channels (and promise, oneshot_slot) carry a single message, so there's nothing to count.
+//
+// Channels always contain a `Metrics` and call it on every send and receive,
+// but without the feature `Metrics` and `Timer` are zero-sized and all their methods are empty,
+// so the compiler removes them entirely (no clock reads, no atomic counters).
+
+#[cfg(feature = "metrics")]
+pub use enabled::ChannelStats;
+#[cfg(feature = "metrics")]
+pub(crate) use enabled::{Metrics, Timer};
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) use disabled::{Metrics, Timer};
+
+#[cfg(feature = "metrics")]
+mod enabled {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::{Duration, Instant};
+
+    /// A snapshot of a channel's counters.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ChannelStats {
+        pub sent: u64,
+        pub received: u64,
+        /// Messages currently waiting in the channel.
+        pub depth: u64,
+        /// The highest `depth` ever seen.
+        pub high_water: u64,
+        /// Total time receivers spent blocked waiting for a message.
+        pub receiver_wait: Duration,
+        /// Total time senders spent blocked waiting for a receiver.
+        pub sender_block: Duration,
+    }
+
+    // The counters are independent of each other and only ever read together in a snapshot,
+    // so Relaxed is enough: a snapshot taken while messages fly by is approximate anyway.
+    // Channels record a message as sent before it can possibly be received,
+    // so `depth` never goes below zero.
+    pub(crate) struct Metrics {
+        sent: AtomicU64,
+        received: AtomicU64,
+        depth: AtomicU64,
+        high_water: AtomicU64,
+        receiver_wait_nanos: AtomicU64,
+        sender_block_nanos: AtomicU64,
+    }
+
+    pub(crate) struct Timer(Instant);
+
+    impl Timer {
+        pub(crate) fn start() -> Self {
+            Timer(Instant::now())
+        }
+
+        fn elapsed_nanos(&self) -> u64 {
+            self.0.elapsed().as_nanos() as u64
+        }
+    }
+
+    impl Metrics {
+        pub(crate) const fn new() -> Self {
+            Metrics {
+                sent: AtomicU64::new(0),
+                received: AtomicU64::new(0),
+                depth: AtomicU64::new(0),
+                high_water: AtomicU64::new(0),
+                receiver_wait_nanos: AtomicU64::new(0),
+                sender_block_nanos: AtomicU64::new(0),
+            }
+        }
+
+        /// `n` messages entered the channel.
+        pub(crate) fn sent(&self, n: usize) {
+            self.sent.fetch_add(n as u64, Relaxed);
+            let depth = self.depth.fetch_add(n as u64, Relaxed) + n as u64;
+            self.high_water.fetch_max(depth, Relaxed);
+        }
+
+        /// `n` messages left the channel through a receiver.
+        pub(crate) fn received(&self, n: usize) {
+            self.received.fetch_add(n as u64, Relaxed);
+            self.depth.fetch_sub(n as u64, Relaxed);
+        }
+
+        /// `n` messages were received without leaving the channel (broadcast).
+        pub(crate) fn delivered(&self, n: usize) {
+            self.received.fetch_add(n as u64, Relaxed);
+        }
+
+        /// `n` messages left the channel without being received (overwritten or dropped).
+        pub(crate) fn discarded(&self, n: usize) {
+            self.depth.fetch_sub(n as u64, Relaxed);
+        }
+
+        pub(crate) fn receiver_waited(&self, timer: Timer) {
+            self.receiver_wait_nanos
+                .fetch_add(timer.elapsed_nanos(), Relaxed);
+        }
+
+        pub(crate) fn sender_blocked(&self, timer: Timer) {
+            self.sender_block_nanos
+                .fetch_add(timer.elapsed_nanos(), Relaxed);
+        }
+
+        pub(crate) fn snapshot(&self) -> ChannelStats {
+            ChannelStats {
+                sent: self.sent.load(Relaxed),
+                received: self.received.load(Relaxed),
+                depth: self.depth.load(Relaxed),
+                high_water: self.high_water.load(Relaxed),
+                receiver_wait: Duration::from_nanos(self.receiver_wait_nanos.load(Relaxed)),
+                sender_block: Duration::from_nanos(self.sender_block_nanos.load(Relaxed)),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod disabled {
+    pub(crate) struct Metrics;
+
+    pub(crate) struct Timer;
+
+    impl Timer {
+        #[inline(always)]
+        pub(crate) fn start() -> Self {
+            Timer
+        }
+    }
+
+    impl Metrics {
+        #[inline(always)]
+        pub(crate) const fn new() -> Self {
+            Metrics
+        }
+
+        #[inline(always)]
+        pub(crate) fn sent(&self, _n: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn received(&self, _n: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn delivered(&self, _n: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn discarded(&self, _n: usize) {}
+
+        #[inline(always)]
+        pub(crate) fn receiver_waited(&self, _timer: Timer) {}
+
+        #[inline(always)]
+        pub(crate) fn sender_blocked(&self, _timer: Timer) {}
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod test {
+    use crate::{broadcast, mpsc, naive_channel, priority_channel, rendezvous, watch};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_naive_channel_stats() {
+        let channel = naive_channel::Channel::new();
+        channel.send_batch(0..5).unwrap();
+        channel.send(5).unwrap();
+        channel.receive();
+        channel.receive();
+        let stats = channel.stats();
+        assert_eq!(stats.sent, 6);
+        assert_eq!(stats.received, 2);
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.high_water, 6);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                channel.close();
+            });
+            assert_eq!(channel.iter().count(), 4);
+        });
+        let stats = channel.stats();
+        assert_eq!(stats.depth, 0);
+        assert!(stats.receiver_wait >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_mpsc_stats() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send_batch(0..3).unwrap();
+        sender.send(3).unwrap();
+        assert_eq!(receiver.receive(), Ok(0));
+        let stats = sender.stats();
+        assert_eq!((stats.sent, stats.received, stats.depth), (4, 1, 3));
+        assert_eq!(stats.high_water, 4);
+        let mut buffer = Vec::new();
+        assert_eq!(receiver.drain_into(&mut buffer, 10), 3);
+        assert_eq!(receiver.stats().received, 4);
+        assert_eq!(receiver.stats().depth, 0);
+    }
+
+    #[test]
+    fn test_priority_channel_stats() {
+        let channel = priority_channel::PriorityChannel::new(2);
+        channel.send_with_priority(1, 0);
+        channel.send_batch_with_priority([2, 3], 1);
+        assert_eq!(channel.try_receive(), Some(1));
+        let stats = channel.stats();
+        assert_eq!((stats.sent, stats.received, stats.depth), (3, 1, 2));
+    }
+
+    #[test]
+    fn test_broadcast_stats() {
+        let (sender, mut receiver) = broadcast::channel(2);
+        let mut other = sender.subscribe();
+        sender.send_batch(0..3).unwrap();
+        assert_eq!(receiver.receive(), Err(broadcast::ReceiveError::Lagged(1)));
+        assert_eq!(receiver.receive(), Ok(1));
+        assert_eq!(other.receive(), Err(broadcast::ReceiveError::Lagged(1)));
+        let stats = sender.stats();
+        assert_eq!(stats.sent, 3);
+        // every receiver counts
+        assert_eq!(stats.received, 1);
+        // the buffer only holds two messages
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.high_water, 2);
+    }
+
+    #[test]
+    fn test_rendezvous_stats() {
+        let (sender, receiver) = rendezvous::channel();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                receiver.receive().unwrap();
+            });
+            sender.send(1).unwrap();
+        });
+        let stats = receiver.stats();
+        assert_eq!((stats.sent, stats.received, stats.depth), (1, 1, 0));
+        assert!(stats.sender_block >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_watch_stats() {
+        let (sender, mut receiver) = watch::channel(0);
+        let mut other = sender.subscribe();
+        // the initial value wasn't sent
+        assert_eq!(sender.stats().depth, 0);
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(receiver.changed(), Ok(()));
+        assert_eq!(*other.borrow_and_update(), 2);
+        // nothing new for this one
+        assert_eq!(*other.borrow_and_update(), 2);
+        let stats = sender.stats();
+        assert_eq!((stats.sent, stats.received), (2, 2));
+        // only the latest value is kept
+        assert_eq!((stats.depth, stats.high_water), (1, 1));
+
+        thread::scope(|s| {
+            let value = receiver.borrow();
+            s.spawn(|| sender.send(3).unwrap());
+            thread::sleep(Duration::from_millis(50));
+            drop(value);
+        });
+        assert!(receiver.stats().sender_block >= Duration::from_millis(50));
+    }
+}
+
+#[cfg(all(test, not(feature = "metrics")))]
+mod test_disabled {
+    use super::*;
+
+    #[test]
+    fn test_zero_sized() {
+        assert_eq!(std::mem::size_of::<Metrics>(), 0);
+        assert_eq!(std::mem::size_of::<Timer>(), 0);
+    }
+}
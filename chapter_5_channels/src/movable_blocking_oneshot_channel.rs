@@ -0,0 +1,176 @@
+use std::mem::MaybeUninit;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+
+use crate::sync::{const_fn, thread, AtomicU8, LoadMut, Thread, UnsafeCell};
+
+const EMPTY: u8 = 0; // no message, nobody waiting
+const WAITING: u8 = 1; // the receiver registered its thread handle and is (about to be) parked
+const READY: u8 = 2; // a message is available
+
+// Same as `blocking_oneshot_channel`, except that the receiving thread is not captured by `split`.
+// Instead the receiver registers its own thread handle right before it goes to sleep,
+// so the `Receiver` can be sent to (and receive on) any thread.
+pub struct Channel<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    // only written by the receiver before the EMPTY -> WAITING transition,
+    // only read by the sender after observing WAITING.
+    receiving_thread: UnsafeCell<Option<Thread>>,
+    state: AtomicU8,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+pub struct Sender<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+// no PhantomData<*const ()>: the receiver is Send (as long as T is)
+pub struct Receiver<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> Channel<T> {
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                receiving_thread: UnsafeCell::new(None),
+                state: AtomicU8::new(EMPTY),
+            }
+        }
+    }
+
+    pub fn split(&mut self) -> (Sender<'_, T>, Receiver<'_, T>) {
+        *self = Self::new();
+        (Sender { channel: self }, Receiver { channel: self })
+    }
+}
+
+impl<T> Sender<'_, T> {
+    pub fn send(self, message: T) {
+        self.channel
+            .message
+            .with_mut(|m| unsafe { (*m).write(message) });
+        // Release publishes the message,
+        // Acquire synchronises with the receiver's registration of its thread handle.
+        if self.channel.state.swap(READY, AcqRel) == WAITING {
+            // Safety: the receiver stored its handle before moving to WAITING
+            // and won't touch it again, so we're the only one accessing it.
+            let receiving_thread = self
+                .channel
+                .receiving_thread
+                .with_mut(|t| unsafe { (*t).take() });
+            if let Some(t) = receiving_thread {
+                t.unpark();
+            }
+        }
+    }
+}
+
+impl<T> Receiver<'_, T> {
+    pub fn is_ready(&self) -> bool {
+        self.channel.state.load(Relaxed) == READY
+    }
+
+    pub fn receive(self) -> T {
+        if self.channel.state.load(Acquire) != READY {
+            // Safety: we're in the EMPTY state, so the sender won't read the handle
+            // until we publish it with the compare-exchange below.
+            self.channel
+                .receiving_thread
+                .with_mut(|t| unsafe { *t = Some(thread::current()) });
+            // Release makes the handle visible to the sender.
+            // If this fails, the message arrived in the meantime and we don't need to wait,
+            // but we still need Acquire to synchronise with the swap that made it READY.
+            if self
+                .channel
+                .state
+                .compare_exchange(EMPTY, WAITING, Release, Acquire)
+                .is_ok()
+            {
+                // park() might return spuriously, so check the state again after every wake up.
+                while self.channel.state.load(Acquire) != READY {
+                    thread::park();
+                }
+            }
+        }
+        // Acquire on the loads above synchronises with the sender's swap.
+        // Reset the state so that dropping the channel doesn't drop the message again.
+        self.channel.state.store(EMPTY, Relaxed);
+        self.channel
+            .message
+            .with(|m| unsafe { (*m).assume_init_read() })
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if self.state.load_mut() == READY {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
+        }
+    }
+}
+
+impl<T> Default for Channel<T>
+where
+    T: Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_receive_on_another_thread() {
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            // the receiver is moved to a thread other than the one that called split
+            let t = s.spawn(move || receiver.receive());
+            // give the receiver time to register itself and park
+            thread::sleep(Duration::from_millis(100));
+            sender.send("hello");
+            assert_eq!(t.join().unwrap(), "hello");
+        });
+    }
+
+    #[test]
+    fn test_message_sent_before_waiting() {
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            sender.send(42);
+            let t = s.spawn(move || {
+                assert!(receiver.is_ready());
+                receiver.receive()
+            });
+            assert_eq!(t.join().unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn test_drops_unreceived_message() {
+        static NUM_DROPS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let mut channel = Channel::new();
+        let (sender, _receiver) = channel.split();
+        sender.send(DetectDrop);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(channel);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    }
+}
@@ -0,0 +1,643 @@
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU8, AtomicUsize};
+use std::sync::Arc;
+use std::thread::{self, Thread};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+
+// Dmitry Vyukov's intrusive MPSC queue:
+// producers atomically swap themselves in as the new tail and then link the previous tail to it,
+// the single consumer follows the `next` pointers from the head.
+// The head always points to a "stub" node whose message was already taken (or never existed),
+// so the queue is never truly empty and producers never have to touch the head.
+//
+// Producers never block: a push is a swap plus a store.
+// Between those two, the consumer can see the queue as empty even though a message is on its way,
+// which is fine, since the producer wakes the consumer up right after linking.
+//
+// Waking up doesn't lock anything either. The receiver keeps its thread handle in `receiver_thread`,
+// and only rewrites it when it was moved to another thread. It announces that it's waiting
+// like in movable_blocking_oneshot_channel, except that it can wait again and again:
+// IDLE -> WAITING by the receiver, then WAITING -> NOTIFYING -> IDLE by the one producer that
+// claims the wake up (or WAITING -> IDLE by the receiver itself, if it didn't need to sleep after all).
+// The handle is only read by that producer, and only rewritten by the receiver while IDLE.
+// Before waiting again, the receiver lets a producer that's still NOTIFYING finish its unpark call.
+
+/// Number of nodes allocated at once by a sender.
+const SEGMENT_LEN: usize = 32;
+
+const IDLE: u8 = 0; // the receiver isn't waiting (or is about to check the queue again)
+const WAITING: u8 = 1; // the receiver is (about to be) parked
+const NOTIFYING: u8 = 2; // a producer is unparking the receiver
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    message: UnsafeCell<MaybeUninit<T>>,
+    segment: *const Segment<T>,
+}
+
+// Nodes are allocated in segments to amortise the allocations.
+// Every sender allocates from its own segment, so handing out a node doesn't need any synchronisation.
+// A segment is freed once all of its nodes were consumed and its sender moved on to the next segment,
+// which is tracked by a reference count (much like our Arc).
+struct Segment<T> {
+    nodes: Box<[Node<T>]>,
+    /// Nodes not released by the consumer yet, plus one while a sender still allocates from it.
+    ref_count: AtomicUsize,
+}
+
+impl<T> Segment<T> {
+    fn allocate(len: usize, owned_by_sender: bool) -> *const Segment<T> {
+        let segment = Box::into_raw(Box::new(Segment {
+            nodes: (0..len)
+                .map(|_| Node {
+                    next: AtomicPtr::new(ptr::null_mut()),
+                    message: UnsafeCell::new(MaybeUninit::uninit()),
+                    segment: ptr::null(),
+                })
+                .collect(),
+            ref_count: AtomicUsize::new(len + owned_by_sender as usize),
+        }));
+        // Safety: we just allocated it and nobody else knows about it yet.
+        for node in unsafe { (*segment).nodes.iter_mut() } {
+            node.segment = segment;
+        }
+        segment
+    }
+
+    fn node(segment: *const Segment<T>, index: usize) -> *mut Node<T> {
+        // Safety: the caller still holds a reference to the segment.
+        unsafe { &(*segment).nodes[index] as *const Node<T> as *mut Node<T> }
+    }
+
+    /// Safety: the caller must own `n` of the segment's references and not use them afterwards.
+    unsafe fn release(segment: *const Segment<T>, n: usize) {
+        // Same as dropping an Arc: Release to make sure we're done with our nodes before they're freed,
+        // Acquire fence to make sure everyone else is.
+        if (*segment).ref_count.fetch_sub(n, Release) == n {
+            fence(Acquire);
+            drop(Box::from_raw(segment as *mut Segment<T>));
+        }
+    }
+}
+
+struct Shared<T> {
+    /// Only touched by the receiver.
+    head: UnsafeCell<*mut Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    sender_count: AtomicUsize,
+    receiver_alive: AtomicBool,
+    /// The thread of the receiver, set before it first waits (on that thread).
+    /// Only written by the receiver in the IDLE state, only read by a producer in the NOTIFYING state.
+    receiver_thread: UnsafeCell<Option<Thread>>,
+    receiver_state: AtomicU8,
+    wakers: Wakers,
+    metrics: Metrics,
+}
+
+unsafe impl<T: Send> Send for Shared<T> {}
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+    segment: Cell<*const Segment<T>>, // null until the first send
+    used: Cell<usize>,                // nodes of `segment` handed out so far
+}
+
+// Moving a sender to another thread moves its segment along with it, which is fine.
+// It's not Sync (because of the Cells): two threads can't allocate from the same segment.
+unsafe impl<T: Send> Send for Sender<T> {}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    _not_sync: PhantomData<Cell<()>>, // single consumer: only one thread may pop at a time
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReceiveError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryReceiveError {
+    Empty,
+    Disconnected,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let stub = Segment::node(Segment::allocate(1, false), 0);
+    let shared = Arc::new(Shared {
+        head: UnsafeCell::new(stub),
+        tail: AtomicPtr::new(stub),
+        sender_count: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        receiver_thread: UnsafeCell::new(None),
+        receiver_state: AtomicU8::new(IDLE),
+        wakers: Wakers::new(),
+        metrics: Metrics::new(),
+    });
+    (
+        Sender::new(shared.clone()),
+        Receiver {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+impl<T> Shared<T> {
+    /// Links the nodes `first..=last` (already linked to each other) to the end of the queue.
+    fn push(&self, first: *mut Node<T>, last: *mut Node<T>) {
+        // AcqRel: Release publishes our nodes to the producer that links to them (or to the consumer),
+        // Acquire gets us the previous tail's initialisation.
+        let prev = self.tail.swap(last, AcqRel);
+        // Release publishes the message to the consumer.
+        unsafe { (*prev).next.store(first, Release) };
+        self.wake_receiver();
+    }
+
+    fn wake_receiver(&self) {
+        // Pairs with the fence in Receiver::receive:
+        // either we see it waiting, or it sees our message.
+        fence(SeqCst);
+        // Acquire: the receiver stored its handle before announcing it's waiting.
+        if self.receiver_state.load(Relaxed) == WAITING
+            && self
+                .receiver_state
+                .compare_exchange(WAITING, NOTIFYING, Acquire, Relaxed)
+                .is_ok()
+        {
+            // Safety: in the NOTIFYING state only we read the handle, and the receiver doesn't write it.
+            if let Some(t) = unsafe { &*self.receiver_thread.get() } {
+                t.unpark();
+            }
+            // Release: we're done with the handle.
+            self.receiver_state.store(IDLE, Release);
+        }
+        // The fence above also covers the selecting threads.
+        self.wakers.wake_all_after_fence();
+    }
+
+    /// Safety: only the receiver (or the last owner) may call this.
+    unsafe fn pop(&self) -> Option<T> {
+        let head = *self.head.get();
+        let next = (*head).next.load(Acquire);
+        if next.is_null() {
+            return None;
+        }
+        // `next` becomes the new stub, the old one isn't referenced anymore.
+        *self.head.get() = next;
+        let message = (*(*next).message.get()).assume_init_read();
+        Segment::release((*head).segment, 1);
+        Some(message)
+    }
+
+    /// Safety: only the receiver may call this.
+    unsafe fn has_message(&self) -> bool {
+        !(**self.head.get()).next.load(Relaxed).is_null()
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Everyone's gone: free the stub and drop the messages that were never received.
+        unsafe {
+            let mut node = *self.head.get_mut();
+            loop {
+                let next = (*node).next.load(Relaxed);
+                Segment::release((*node).segment, 1);
+                if next.is_null() {
+                    break;
+                }
+                (*(*next).message.get()).assume_init_drop();
+                node = next;
+            }
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    fn new(shared: Arc<Shared<T>>) -> Self {
+        Sender {
+            shared,
+            segment: Cell::new(ptr::null()),
+            used: Cell::new(0),
+        }
+    }
+
+    fn allocate_node(&self) -> *mut Node<T> {
+        if self.segment.get().is_null() || self.used.get() == SEGMENT_LEN {
+            if !self.segment.get().is_null() {
+                // Safety: all nodes were handed out, give up our own reference.
+                unsafe { Segment::release(self.segment.get(), 1) };
+            }
+            self.segment.set(Segment::allocate(SEGMENT_LEN, true));
+            self.used.set(0);
+        }
+        let node = Segment::node(self.segment.get(), self.used.get());
+        self.used.set(self.used.get() + 1);
+        node
+    }
+
+    /// Never blocks.
+    /// Gives the message back if the receiver is gone.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        if !self.shared.receiver_alive.load(Relaxed) {
+            return Err(SendError(message));
+        }
+        let node = self.allocate_node();
+        // Safety: the node is fresh, nobody else can see it until it's pushed.
+        unsafe { (*(*node).message.get()).write(message) };
+        // before the push, so the receiver can't count it as received first
+        self.shared.metrics.sent(1);
+        self.shared.push(node, node);
+        Ok(())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> Sender<T> {
+    /// Links all messages to each other first, and then to the queue with a single swap.
+    /// Gives the messages back if the receiver is gone.
+    pub fn send_batch(
+        &self,
+        messages: impl IntoIterator<Item = T>,
+    ) -> Result<(), SendError<Vec<T>>> {
+        if !self.shared.receiver_alive.load(Relaxed) {
+            return Err(SendError(messages.into_iter().collect()));
+        }
+        let mut batch = Batch {
+            first: ptr::null_mut(),
+            last: ptr::null_mut(),
+            n: 0,
+        };
+        for message in messages {
+            let node = self.allocate_node();
+            // Safety: the nodes aren't visible to anyone else until they're pushed.
+            // The release swap in push publishes these relaxed links along with the messages.
+            unsafe {
+                (*(*node).message.get()).write(message);
+                if batch.last.is_null() {
+                    batch.first = node;
+                } else {
+                    (*batch.last).next.store(node, Relaxed);
+                }
+            }
+            batch.last = node;
+            batch.n += 1;
+        }
+        let batch = mem::ManuallyDrop::new(batch);
+        if !batch.first.is_null() {
+            self.shared.metrics.sent(batch.n);
+            self.shared.push(batch.first, batch.last);
+        }
+        Ok(())
+    }
+}
+
+/// The nodes of a `send_batch` that aren't pushed yet.
+/// Only dropped if the iterator panicked: then the messages are dropped and the nodes given back.
+struct Batch<T> {
+    first: *mut Node<T>,
+    last: *mut Node<T>,
+    n: usize,
+}
+
+impl<T> Drop for Batch<T> {
+    fn drop(&mut self) {
+        let mut node = self.first;
+        while !node.is_null() {
+            // Safety: nobody else has seen these nodes, and each one holds a reference to its segment.
+            unsafe {
+                let next = if node == self.last {
+                    ptr::null_mut()
+                } else {
+                    (*node).next.load(Relaxed)
+                };
+                (*(*node).message.get()).assume_init_drop();
+                Segment::release((*node).segment, 1);
+                node = next;
+            }
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.sender_count.fetch_add(1, Relaxed);
+        // the clone gets its own segment
+        Sender::new(self.shared.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let segment = self.segment.get();
+        if !segment.is_null() {
+            // Safety: the nodes we never handed out, and our own reference.
+            unsafe { Segment::release(segment, SEGMENT_LEN - self.used.get() + 1) };
+        }
+        // Release: the receiver must see all our messages when it sees the count drop.
+        if self.shared.sender_count.fetch_sub(1, Release) == 1 {
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_receive(&self) -> Result<T, TryReceiveError> {
+        // Safety: we're the only receiver, and we're not Sync.
+        let message = match unsafe { self.shared.pop() } {
+            Some(message) => message,
+            // The last message might have been pushed right before the last sender was dropped.
+            None if self.shared.sender_count.load(Acquire) == 0 => {
+                unsafe { self.shared.pop() }.ok_or(TryReceiveError::Disconnected)?
+            }
+            None => return Err(TryReceiveError::Empty),
+        };
+        self.shared.metrics.received(1);
+        Ok(message)
+    }
+
+    /// Blocks until a message is available.
+    /// Fails once all senders are gone and the queue is empty.
+    pub fn receive(&self) -> Result<T, ReceiveError> {
+        let timer = Timer::start();
+        let result = loop {
+            match self.try_receive() {
+                Ok(message) => break Ok(message),
+                Err(TryReceiveError::Disconnected) => break Err(ReceiveError),
+                Err(TryReceiveError::Empty) => {}
+            }
+            self.register_thread();
+            // Release publishes the handle to the producer that claims the wake up.
+            self.shared.receiver_state.store(WAITING, Release);
+            // Pairs with the fence in Shared::wake_receiver.
+            fence(SeqCst);
+            if unsafe { self.shared.has_message() } || self.shared.sender_count.load(Relaxed) == 0 {
+                // If this fails, a producer is already waking us up, which is harmless.
+                let _ = self
+                    .shared
+                    .receiver_state
+                    .compare_exchange(WAITING, IDLE, Relaxed, Relaxed);
+                continue;
+            }
+            // park() might return spuriously, the loop takes care of that.
+            thread::park();
+        };
+        self.shared.metrics.receiver_waited(timer);
+        result
+    }
+
+    /// Gets back to IDLE and makes sure the handle in `receiver_thread` is this thread's.
+    fn register_thread(&self) {
+        // Take back a WAITING left over from a spurious wake up, or wait until a producer
+        // in the middle of waking us up is done with the handle. (Storing WAITING over its NOTIFYING
+        // would get lost when it stores IDLE.) That's an unpark call at most, and producers never wait for us.
+        loop {
+            match self
+                .shared
+                .receiver_state
+                .compare_exchange(WAITING, IDLE, Relaxed, Acquire)
+            {
+                Ok(_) | Err(IDLE) => break,
+                Err(_) => thread::yield_now(),
+            }
+        }
+        // Safety: we're IDLE, so no producer reads the handle until we store WAITING again.
+        let registered = unsafe { &mut *self.shared.receiver_thread.get() };
+        // Only replace it when the receiver was moved to another thread (or never waited before).
+        if !registered
+            .as_ref()
+            .is_some_and(|t| t.id() == thread::current().id())
+        {
+            *registered = Some(thread::current());
+        }
+    }
+
+    /// Blocks until a message is available, then moves up to `max` messages into `buffer`.
+    /// Returns the number of messages moved, zero once all senders are gone and the queue is empty.
+    pub fn drain_into(&self, buffer: &mut Vec<T>, max: usize) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        let Ok(message) = self.receive() else {
+            return 0;
+        };
+        buffer.push(message);
+        let mut n = 1;
+        // Safety: we're the only receiver.
+        while n < max {
+            let Some(message) = (unsafe { self.shared.pop() }) else {
+                break;
+            };
+            buffer.push(message);
+            n += 1;
+        }
+        self.shared.metrics.received(n - 1);
+        n
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        // Safety: we're the only receiver.
+        let has_message = unsafe { self.shared.has_message() };
+        has_message || self.shared.sender_count.load(Relaxed) == 0
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Relaxed);
+        // Drop what's there already; what's sent afterwards is dropped by Shared::drop.
+        while unsafe { self.shared.pop() }.is_some() {
+            self.shared.metrics.discarded(1);
+        }
+    }
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        Receiver::is_ready(self)
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.shared.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.shared.wakers.unregister(waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::time::Duration;
+
+    #[test]
+    fn test_fifo_per_sender() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for j in 0..1000 {
+                        sender.send((i, j)).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            let mut last = [None; 4];
+            let mut count = 0;
+            while let Ok((i, j)) = receiver.receive() {
+                // messages from the same sender arrive in order
+                assert!(last[i].is_none_or(|l| l < j));
+                last[i] = Some(j);
+                count += 1;
+            }
+            assert_eq!(count, 4000);
+        });
+    }
+
+    #[test]
+    fn test_receiver_parks_until_send() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let t = s.spawn(move || receiver.receive());
+            thread::sleep(Duration::from_millis(50));
+            sender.send("hello").unwrap();
+            assert_eq!(t.join().unwrap(), Ok("hello"));
+        });
+    }
+
+    #[test]
+    fn test_receiver_moved_after_waiting() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            // the receiver waits once on one thread, then on another one
+            let t = s.spawn(move || {
+                let first = receiver.receive();
+                (first, receiver)
+            });
+            thread::sleep(Duration::from_millis(50));
+            sender.send(1).unwrap();
+            let (first, receiver) = t.join().unwrap();
+            assert_eq!(first, Ok(1));
+            let t = s.spawn(move || receiver.receive());
+            thread::sleep(Duration::from_millis(50));
+            sender.send(2).unwrap();
+            assert_eq!(t.join().unwrap(), Ok(2));
+        });
+    }
+
+    #[test]
+    fn test_disconnection() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Ok(1));
+        assert_eq!(receiver.try_receive(), Err(TryReceiveError::Disconnected));
+        assert_eq!(receiver.receive(), Err(ReceiveError));
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.send(2), Err(SendError(2)));
+    }
+
+    #[test]
+    fn test_send_batch_and_drain_into() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || sender.send_batch(i * 100..(i + 1) * 100).unwrap());
+            }
+            drop(sender);
+            let mut buffer = Vec::new();
+            while receiver.drain_into(&mut buffer, 64) > 0 {}
+            buffer.sort();
+            assert_eq!(buffer, (0..400).collect::<Vec<_>>());
+        });
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.send_batch([1, 2]), Err(SendError(vec![1, 2])));
+    }
+
+    #[test]
+    fn test_send_batch_panicking_iterator() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let (sender, receiver) = channel();
+        // more than a segment, so some of it is given back to a segment the sender already moved on from
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            sender.send_batch((0..SEGMENT_LEN + 5).map(|i| {
+                assert!(i < SEGMENT_LEN + 4, "iterator panicked");
+                DetectDrop(i)
+            }))
+        }));
+        assert!(result.is_err());
+        // the messages made so far were dropped, and nothing was sent
+        assert_eq!(NUM_DROPS.load(Relaxed), SEGMENT_LEN + 4);
+        assert_eq!(receiver.try_receive().err(), Some(TryReceiveError::Empty));
+
+        // the sender is still usable
+        assert!(sender.send(DetectDrop(100)).is_ok());
+        assert_eq!(receiver.receive().unwrap().0, 100);
+        drop((sender, receiver));
+        assert_eq!(NUM_DROPS.load(Relaxed), SEGMENT_LEN + 5);
+    }
+
+    #[test]
+    fn test_no_leaks() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let (sender, receiver) = channel();
+        // more than one segment
+        for _ in 0..SEGMENT_LEN * 2 + 1 {
+            assert!(sender.send(DetectDrop).is_ok());
+        }
+        assert!(receiver.receive().is_ok());
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        drop(receiver);
+        assert_eq!(NUM_DROPS.load(Relaxed), SEGMENT_LEN * 2 + 1);
+        // the message is given back (and dropped here) once the receiver is gone
+        assert!(sender.send(DetectDrop).is_err());
+        drop(sender);
+        assert_eq!(NUM_DROPS.load(Relaxed), SEGMENT_LEN * 2 + 2);
+    }
+}
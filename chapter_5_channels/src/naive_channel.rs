@@ -1,13 +1,27 @@
-use std::{
-    collections::VecDeque,
-    sync::{Condvar, Mutex},
-};
+use std::collections::VecDeque;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+use crate::sync::{Condvar, Mutex, MutexGuard};
 
 pub struct Channel<T> {
-    queue: Mutex<VecDeque<T>>,
+    queue: Mutex<Queue<T>>,
     item_ready: Condvar,
+    wakers: Wakers, // threads waiting in a Select
+    metrics: Metrics,
+}
+
+struct Queue<T> {
+    messages: VecDeque<T>,
+    closed: bool,
 }
 
+/// The channel was closed, the message is given back.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Closed<T>(pub T);
+
 impl<T> Default for Channel<T> {
     fn default() -> Self {
         Self::new()
@@ -21,23 +35,245 @@ impl<T> Default for Channel<T> {
 impl<T> Channel<T> {
     pub fn new() -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
+            queue: Mutex::new(Queue {
+                messages: VecDeque::new(),
+                closed: false,
+            }),
             item_ready: Condvar::new(),
+            wakers: Wakers::new(),
+            metrics: Metrics::new(),
         }
     }
 
-    pub fn send(&self, message: T) {
-        self.queue.lock().unwrap().push_back(message);
+    fn lock(&self) -> MutexGuard<'_, Queue<T>> {
+        self.queue.lock().unwrap()
+    }
+
+    pub fn send(&self, message: T) -> Result<(), Closed<T>> {
+        let mut queue = self.lock();
+        if queue.closed {
+            return Err(Closed(message));
+        }
+        queue.messages.push_back(message);
+        self.metrics.sent(1);
+        drop(queue);
         self.item_ready.notify_one();
+        self.wakers.wake_all();
+        Ok(())
     }
 
-    pub fn receive(&self) -> T {
-        let mut b = self.queue.lock().unwrap();
-        loop {
-            if let Some(message) = b.pop_front() {
-                return message;
+    /// Sends all messages while holding the lock only once.
+    /// The iterator runs before locking: if it panics or uses the channel, the lock isn't held.
+    pub fn send_batch(&self, messages: impl IntoIterator<Item = T>) -> Result<(), Closed<Vec<T>>> {
+        let messages: Vec<T> = messages.into_iter().collect();
+        let mut queue = self.lock();
+        if queue.closed {
+            return Err(Closed(messages));
+        }
+        let sent = messages.len();
+        queue.messages.extend(messages);
+        self.metrics.sent(sent);
+        drop(queue);
+        // one receiver per message at most, waking up more is pointless
+        match sent {
+            0 => return Ok(()),
+            1 => self.item_ready.notify_one(),
+            _ => self.item_ready.notify_all(),
+        }
+        self.wakers.wake_all();
+        Ok(())
+    }
+
+    /// Blocks until a message is available.
+    /// Returns `None` once the channel is closed and all messages were received.
+    pub fn receive(&self) -> Option<T> {
+        let timer = Timer::start();
+        let mut b = self.lock();
+        let message = loop {
+            if let Some(message) = b.messages.pop_front() {
+                self.metrics.received(1);
+                break Some(message);
+            }
+            if b.closed {
+                break None;
             }
             b = self.item_ready.wait(b).unwrap();
+        };
+        drop(b);
+        self.metrics.receiver_waited(timer);
+        message
+    }
+
+    /// Blocks until a message is available,
+    /// then moves up to `max` messages into `buffer` while holding the lock only once.
+    /// Returns the number of messages moved, zero once the channel is closed and drained.
+    pub fn drain_into(&self, buffer: &mut Vec<T>, max: usize) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        let timer = Timer::start();
+        let mut queue = self.lock();
+        while queue.messages.is_empty() && !queue.closed {
+            queue = self.item_ready.wait(queue).unwrap();
         }
+        let n = max.min(queue.messages.len());
+        buffer.extend(queue.messages.drain(..n));
+        self.metrics.received(n);
+        drop(queue);
+        self.metrics.receiver_waited(timer);
+        n
+    }
+
+    /// No more messages can be sent after this.
+    /// The messages already in the channel can still be received.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        // wake up everyone waiting for a message that will never come
+        self.item_ready.notify_all();
+        self.wakers.wake_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.metrics.snapshot()
+    }
+
+    /// Receives messages until the channel is closed and drained.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { channel: self }
+    }
+}
+
+pub struct Iter<'a, T> {
+    channel: &'a Channel<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.channel.receive()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Channel<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Selectable for Channel<T> {
+    fn is_ready(&self) -> bool {
+        let queue = self.lock();
+        !queue.messages.is_empty() || queue.closed
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.wakers.unregister(waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn test_send_batch_and_drain_into() {
+        let channel = Channel::new();
+        channel.send_batch(0..10).unwrap();
+        channel.send_batch([]).unwrap();
+
+        let mut buffer = Vec::new();
+        assert_eq!(channel.drain_into(&mut buffer, 4), 4);
+        assert_eq!(channel.drain_into(&mut buffer, 100), 6);
+        assert_eq!(buffer, (0..10).collect::<Vec<_>>());
+        assert_eq!(channel.drain_into(&mut buffer, 0), 0);
+    }
+
+    #[test]
+    fn test_send_batch_panicking_iterator() {
+        let channel = Channel::new();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            channel.send_batch((0..5).inspect(|&i| assert!(i < 3, "iterator panicked")))
+        }));
+        assert!(result.is_err());
+        // nothing was sent, and the lock isn't poisoned
+        assert!(channel.is_empty());
+        // the iterator can use the channel itself
+        channel
+            .send_batch((0..2).map(|i| i + channel.len()))
+            .unwrap();
+        assert_eq!(channel.receive(), Some(0));
+        assert_eq!(channel.receive(), Some(1));
+    }
+
+    #[test]
+    fn test_batches_across_threads() {
+        let channel = Channel::new();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let channel = &channel;
+                s.spawn(move || channel.send_batch(i * 100..(i + 1) * 100).unwrap());
+            }
+            let mut buffer = Vec::new();
+            while buffer.len() < 400 {
+                channel.drain_into(&mut buffer, 64);
+            }
+            buffer.sort();
+            assert_eq!(buffer, (0..400).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn test_close() {
+        let channel = Channel::new();
+        channel.send(1).unwrap();
+        channel.send(2).unwrap();
+        assert_eq!(channel.len(), 2);
+        channel.close();
+        assert!(channel.is_closed());
+        assert_eq!(channel.send(3), Err(Closed(3)));
+        assert_eq!(channel.send_batch([4, 5]), Err(Closed(vec![4, 5])));
+        // what was sent before closing can still be received
+        assert_eq!(channel.receive(), Some(1));
+        assert_eq!(channel.receive(), Some(2));
+        assert_eq!(channel.receive(), None);
+        assert!(channel.is_empty());
+        assert_eq!(channel.drain_into(&mut Vec::new(), 10), 0);
+    }
+
+    #[test]
+    fn test_iter_ends_on_close() {
+        let channel = Channel::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..5 {
+                    channel.send(i).unwrap();
+                }
+                channel.close();
+            });
+            assert_eq!(channel.iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        });
     }
 }
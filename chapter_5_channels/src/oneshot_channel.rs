@@ -1,5 +1,7 @@
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool};
+
+use crate::sync::{const_fn, AtomicBool, LoadMut, UnsafeCell};
 
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>, // unsafe barebones alternative to Option<T>
@@ -8,11 +10,13 @@ pub struct Channel<T> {
 }
 
 impl<T> Channel<T> {
-    pub const fn new() -> Self {
-        Self {
-            message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
-            in_use: AtomicBool::new(false),
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                ready: AtomicBool::new(false),
+                in_use: AtomicBool::new(false),
+            }
         }
     }
 
@@ -21,7 +25,7 @@ impl<T> Channel<T> {
         if self.in_use.swap(true, Relaxed) {
             panic!("can't send more than one message!");
         }
-        unsafe { (*self.message.get()).write(message) };
+        self.message.with_mut(|m| unsafe { (*m).write(message) });
         self.ready.store(true, Release);
     }
 
@@ -42,7 +46,7 @@ impl<T> Channel<T> {
             panic!("no message available!");
         }
         // Safety: We've just checked (and reset) the ready flag.
-        unsafe { (*self.message.get()).assume_init_read() }
+        self.message.with(|m| unsafe { (*m).assume_init_read() })
     }
 }
 
@@ -55,8 +59,9 @@ impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         // don't need atomic operation:
         // object can only be dropped if it is fully owned by whichever thread is dropping it, with no outstanding borrows
-        if *self.ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() }
+        if self.ready.load_mut() {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
         }
     }
 }
@@ -0,0 +1,373 @@
+use std::mem::MaybeUninit;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use crate::sync::{const_fn, hint, AtomicBool, AtomicU64, AtomicUsize, LoadMut, UnsafeCell};
+
+// The lowest two bits of the state hold the phase (like in `mem_opt_oneshot_channel`),
+// the remaining bits hold the generation, which is bumped every time the slot is recycled.
+const EMPTY: usize = 0;
+const WRITING: usize = 1;
+const READY: usize = 2;
+const READING: usize = 3;
+const PHASE: usize = 0b11;
+const ONE_GENERATION: usize = PHASE + 1;
+
+/// A oneshot channel that can be reused after the receiver is done with it,
+/// without allocating a new channel for every message.
+///
+/// Dropping the `Receiver` recycles the slot: the generation is bumped,
+/// so a `Sender` of a previous generation can no longer write into it.
+pub struct OneshotSlot<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicUsize,
+    claimed: AtomicBool, // a sender/receiver pair currently exists
+}
+
+unsafe impl<T> Sync for OneshotSlot<T> where T: Send {}
+
+pub struct Sender<'a, T> {
+    slot: &'a OneshotSlot<T>,
+    generation: usize,
+}
+
+pub struct Receiver<'a, T> {
+    slot: &'a OneshotSlot<T>,
+    generation: usize,
+    // set when the slot was handed out by a pool, to give it back on drop
+    pool: Option<(&'a SlotPool<T>, u32)>,
+}
+
+impl<T> OneshotSlot<T> {
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                state: AtomicUsize::new(EMPTY),
+                claimed: AtomicBool::new(false),
+            }
+        }
+    }
+
+    /// Returns `None` if the receiver of the previous split hasn't been dropped yet.
+    pub fn split(&self) -> Option<(Sender<'_, T>, Receiver<'_, T>)> {
+        // Acquire synchronises with the release-store in Receiver::drop,
+        // so the previous message is completely gone.
+        if self.claimed.swap(true, Acquire) {
+            return None;
+        }
+        let generation = self.state.load(Relaxed) & !PHASE;
+        Some((
+            Sender {
+                slot: self,
+                generation,
+            },
+            Receiver {
+                slot: self,
+                generation,
+                pool: None,
+            },
+        ))
+    }
+
+    pub fn generation(&self) -> usize {
+        self.state.load(Relaxed) / ONE_GENERATION
+    }
+}
+
+impl<T> Sender<'_, T> {
+    /// Gives the message back if the slot was recycled in the meantime (i.e. this sender is stale).
+    pub fn send(self, message: T) -> Result<(), T> {
+        if self
+            .slot
+            .state
+            .compare_exchange(
+                self.generation | EMPTY,
+                self.generation | WRITING,
+                Acquire,
+                Relaxed,
+            )
+            .is_err()
+        {
+            return Err(message);
+        }
+        self.slot
+            .message
+            .with_mut(|m| unsafe { (*m).write(message) });
+        self.slot.state.store(self.generation | READY, Release);
+        Ok(())
+    }
+}
+
+impl<T> Receiver<'_, T> {
+    pub fn is_ready(&self) -> bool {
+        self.slot.state.load(Relaxed) == self.generation | READY
+    }
+
+    /// Panics if no message is available yet.
+    ///
+    /// Tip: Use `is_ready` to check first.
+    pub fn receive(self) -> T {
+        if self
+            .slot
+            .state
+            .compare_exchange(
+                self.generation | READY,
+                self.generation | READING,
+                Acquire,
+                Relaxed,
+            )
+            .is_err()
+        {
+            panic!("no message available!");
+        }
+        self.slot
+            .message
+            .with(|m| unsafe { (*m).assume_init_read() })
+        // the slot is recycled when self is dropped, right after this
+    }
+}
+
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        let next = self.generation.wrapping_add(ONE_GENERATION) | EMPTY;
+        loop {
+            let state = self.slot.state.load(Acquire);
+            match state & PHASE {
+                // Nothing was sent: bumping the generation makes the sender stale.
+                EMPTY => {
+                    if self
+                        .slot
+                        .state
+                        .compare_exchange(state, next, Relaxed, Relaxed)
+                        .is_ok()
+                    {
+                        break;
+                    }
+                }
+                // The sender is halfway through writing, wait for it to finish.
+                WRITING => hint::spin_loop(),
+                // Sent but never received: drop the message to avoid leaking it.
+                READY => {
+                    self.slot
+                        .message
+                        .with_mut(|m| unsafe { (*m).assume_init_drop() });
+                    self.slot.state.store(next, Release);
+                    break;
+                }
+                // Already received.
+                _ => {
+                    self.slot.state.store(next, Release);
+                    break;
+                }
+            }
+        }
+        self.slot.claimed.store(false, Release);
+        if let Some((pool, index)) = self.pool {
+            pool.release(index);
+        }
+    }
+}
+
+impl<T> Drop for OneshotSlot<T> {
+    fn drop(&mut self) {
+        // Receivers borrow the slot, so they're all gone and have already recycled it.
+        // The only way to still be READY is a receiver that was leaked with mem::forget.
+        if self.state.load_mut() & PHASE == READY {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
+        }
+    }
+}
+
+impl<T> Default for OneshotSlot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const NONE: u32 = u32::MAX;
+
+/// A fixed set of `OneshotSlot`s handed out without locking.
+///
+/// The free slots form a Treiber stack of indices.
+/// The head carries a tag that is incremented on every change,
+/// so a head that was popped and pushed back in the meantime (ABA) isn't mistaken for an unchanged one.
+pub struct SlotPool<T> {
+    slots: Box<[OneshotSlot<T>]>,
+    // next free index for each free slot
+    next: Box<[AtomicU64]>,
+    // tag in the upper 32 bits, index of the first free slot in the lower 32 bits
+    head: AtomicU64,
+}
+
+impl<T> SlotPool<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity < NONE as usize, "capacity too large");
+        Self {
+            slots: (0..capacity).map(|_| OneshotSlot::new()).collect(),
+            next: (0..capacity)
+                .map(|i| {
+                    AtomicU64::new(if i + 1 < capacity {
+                        i as u64 + 1
+                    } else {
+                        NONE as u64
+                    })
+                })
+                .collect(),
+            head: AtomicU64::new(if capacity > 0 { 0 } else { NONE as u64 }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns `None` if all slots are in use.
+    /// The slot goes back to the pool when the `Receiver` is dropped.
+    pub fn acquire(&self) -> Option<(Sender<'_, T>, Receiver<'_, T>)> {
+        // Acquire synchronises with the release in `release`, so the slot is fully recycled.
+        let mut head = self.head.load(Acquire);
+        let index = loop {
+            let index = head as u32;
+            if index == NONE {
+                return None;
+            }
+            let next = self.next[index as usize].load(Relaxed);
+            let new_head = tag(head).wrapping_add(1) << 32 | next;
+            match self
+                .head
+                .compare_exchange_weak(head, new_head, Acquire, Acquire)
+            {
+                Ok(_) => break index,
+                Err(h) => head = h,
+            }
+        };
+        let (sender, mut receiver) = self.slots[index as usize]
+            .split()
+            .expect("a free slot has no receiver");
+        receiver.pool = Some((self, index));
+        Some((sender, receiver))
+    }
+
+    fn release(&self, index: u32) {
+        let mut head = self.head.load(Relaxed);
+        loop {
+            self.next[index as usize].store(head & u32::MAX as u64, Relaxed);
+            let new_head = tag(head).wrapping_add(1) << 32 | index as u64;
+            match self
+                .head
+                .compare_exchange_weak(head, new_head, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+fn tag(head: u64) -> u64 {
+    head >> 32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_reuse_slot() {
+        let slot = OneshotSlot::new();
+        for i in 0..3 {
+            let (sender, receiver) = slot.split().unwrap();
+            // only one pair at a time
+            assert!(slot.split().is_none());
+            thread::scope(|s| {
+                s.spawn(move || sender.send(i).unwrap());
+            });
+            assert!(receiver.is_ready());
+            assert_eq!(receiver.receive(), i);
+        }
+        assert_eq!(slot.generation(), 3);
+    }
+
+    #[test]
+    fn test_stale_sender_is_rejected() {
+        let slot = OneshotSlot::new();
+        let (stale_sender, receiver) = slot.split().unwrap();
+        drop(receiver);
+
+        let (sender, receiver) = slot.split().unwrap();
+        assert_eq!(stale_sender.send("stale"), Err("stale"));
+        assert!(!receiver.is_ready());
+        sender.send("fresh").unwrap();
+        assert_eq!(receiver.receive(), "fresh");
+    }
+
+    #[test]
+    fn test_drops_unreceived_message() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let slot = OneshotSlot::new();
+        let (sender, receiver) = slot.split().unwrap();
+        assert!(sender.send(DetectDrop).is_ok());
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(receiver);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        // the slot is empty again
+        drop(slot);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_pool_recycles_slots() {
+        let pool = SlotPool::new(2);
+        let a = pool.acquire().unwrap();
+        let b = pool.acquire().unwrap();
+        assert!(pool.acquire().is_none());
+
+        drop(a);
+        let (sender, receiver) = pool.acquire().unwrap();
+        sender.send(1).unwrap();
+        assert_eq!(receiver.receive(), 1);
+        drop(b);
+
+        assert!(pool.acquire().is_some());
+        assert!(pool.acquire().is_some());
+    }
+
+    #[test]
+    fn test_pool_concurrent() {
+        let pool = SlotPool::new(4);
+        let received = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for i in 0..200 {
+                        let Some((sender, receiver)) = pool.acquire() else {
+                            thread::yield_now();
+                            continue;
+                        };
+                        thread::scope(|s| {
+                            s.spawn(move || sender.send(i).unwrap());
+                        });
+                        assert_eq!(receiver.receive(), i);
+                        received.fetch_add(1, Relaxed);
+                    }
+                });
+            }
+        });
+        assert!(received.load(Relaxed) > 0);
+        // every slot made it back to the pool
+        let all: Vec<_> = (0..4).map(|_| pool.acquire().unwrap()).collect();
+        assert!(pool.acquire().is_none());
+        drop(all);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+
+// Like naive_channel, but with one queue ("lane") per priority.
+// A receive takes the oldest message of the highest priority lane that has one,
+// so high priority messages overtake everything of lower priority that's still queued.
+//
+// Without fairness, a steady stream of high priority messages starves the lower lanes.
+// With fairness, every non-empty lane counts how many receives went to a higher lane in a row,
+// and once a lane reaches the limit, the lowest lane at the limit gets the next receive.
+// That receive doesn't count against the other lanes at the limit, since they're all higher,
+// so no lane is ever skipped more than the limit.
+pub struct PriorityChannel<T> {
+    state: Mutex<State<T>>,
+    item_ready: Condvar,
+    wakers: Wakers,
+    metrics: Metrics,
+}
+
+struct State<T> {
+    lanes: Vec<VecDeque<T>>, // lane 0 has the highest priority
+    skipped: Vec<usize>,     // receives that went to a higher lane while this one was waiting
+    max_skipped: Option<usize>,
+}
+
+impl<T> PriorityChannel<T> {
+    /// Panics if `lanes` is zero.
+    pub fn new(lanes: usize) -> Self {
+        Self::with_fairness_option(lanes, None)
+    }
+
+    /// A lane is served after at most `max_skipped` receives went to higher lanes while it had messages.
+    pub fn with_fairness(lanes: usize, max_skipped: usize) -> Self {
+        Self::with_fairness_option(lanes, Some(max_skipped))
+    }
+
+    fn with_fairness_option(lanes: usize, max_skipped: Option<usize>) -> Self {
+        assert!(lanes > 0, "a channel needs at least one lane");
+        Self {
+            state: Mutex::new(State {
+                lanes: (0..lanes).map(|_| VecDeque::new()).collect(),
+                skipped: vec![0; lanes],
+                max_skipped,
+            }),
+            item_ready: Condvar::new(),
+            wakers: Wakers::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.lock().lanes.len()
+    }
+
+    /// Priority 0 is the highest.
+    /// Priorities beyond the last lane end up in the last lane.
+    pub fn send_with_priority(&self, message: T, priority: u8) {
+        let mut state = self.lock();
+        let lane = (priority as usize).min(state.lanes.len() - 1);
+        state.lanes[lane].push_back(message);
+        self.metrics.sent(1);
+        drop(state);
+        self.item_ready.notify_one();
+        self.wakers.wake_all();
+    }
+
+    /// Sends all messages with the same priority while holding the lock only once.
+    /// The iterator runs before locking: if it panics or uses the channel, the lock isn't held.
+    pub fn send_batch_with_priority(&self, messages: impl IntoIterator<Item = T>, priority: u8) {
+        let messages: Vec<T> = messages.into_iter().collect();
+        let sent = messages.len();
+        let mut state = self.lock();
+        let lane = (priority as usize).min(state.lanes.len() - 1);
+        state.lanes[lane].extend(messages);
+        self.metrics.sent(sent);
+        drop(state);
+        match sent {
+            0 => return,
+            1 => self.item_ready.notify_one(),
+            _ => self.item_ready.notify_all(),
+        }
+        self.wakers.wake_all();
+    }
+
+    pub fn try_receive(&self) -> Option<T> {
+        let message = self.lock().pop()?;
+        self.metrics.received(1);
+        Some(message)
+    }
+
+    pub fn receive(&self) -> T {
+        let timer = Timer::start();
+        let mut state = self.lock();
+        let message = loop {
+            if let Some(message) = state.pop() {
+                break message;
+            }
+            state = self.item_ready.wait(state).unwrap();
+        };
+        drop(state);
+        self.metrics.received(1);
+        self.metrics.receiver_waited(timer);
+        message
+    }
+
+    /// Returns `None` if nothing arrived within `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        let timer = Timer::start();
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        let message = loop {
+            if let Some(message) = state.pop() {
+                break Some(message);
+            }
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) => state = self.item_ready.wait_timeout(state, timeout).unwrap().0,
+                None => break None,
+            }
+        };
+        drop(state);
+        self.metrics.received(message.is_some() as usize);
+        self.metrics.receiver_waited(timer);
+        message
+    }
+
+    /// Blocks until a message is available,
+    /// then moves up to `max` messages into `buffer` (in priority order) while holding the lock only once.
+    /// Returns the number of messages moved.
+    pub fn drain_into(&self, buffer: &mut Vec<T>, max: usize) -> usize {
+        if max == 0 {
+            return 0;
+        }
+        let timer = Timer::start();
+        let mut state = self.lock();
+        let n = loop {
+            let len_before = buffer.len();
+            buffer.extend(std::iter::from_fn(|| state.pop()).take(max));
+            let n = buffer.len() - len_before;
+            if n > 0 {
+                break n;
+            }
+            state = self.item_ready.wait(state).unwrap();
+        };
+        drop(state);
+        self.metrics.received(n);
+        self.metrics.receiver_waited(timer);
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().lanes.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.metrics.snapshot()
+    }
+}
+
+impl<T> State<T> {
+    fn pop(&mut self) -> Option<T> {
+        let highest = self.lanes.iter().position(|lane| !lane.is_empty())?;
+        let mut lane = highest;
+        if let Some(max_skipped) = self.max_skipped {
+            // the lowest lane that was skipped too often, if any
+            if let Some(starved) = (highest + 1..self.lanes.len())
+                .rev()
+                .find(|&l| !self.lanes[l].is_empty() && self.skipped[l] >= max_skipped)
+            {
+                lane = starved;
+            }
+        }
+        for l in lane + 1..self.lanes.len() {
+            if !self.lanes[l].is_empty() {
+                self.skipped[l] += 1;
+            }
+        }
+        self.skipped[lane] = 0;
+        self.lanes[lane].pop_front()
+    }
+}
+
+impl<T> Selectable for PriorityChannel<T> {
+    fn is_ready(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.wakers.unregister(waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn test_high_priority_overtakes() {
+        let channel = PriorityChannel::new(3);
+        channel.send_with_priority("bulk 1", 2);
+        channel.send_with_priority("bulk 2", 2);
+        channel.send_with_priority("normal", 1);
+        channel.send_with_priority("control", 0);
+        // beyond the last lane
+        channel.send_with_priority("bulk 3", 200);
+        assert_eq!(channel.len(), 5);
+
+        let received: Vec<_> = (0..5).map(|_| channel.receive()).collect();
+        assert_eq!(
+            received,
+            ["control", "normal", "bulk 1", "bulk 2", "bulk 3"]
+        );
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn test_fairness_prevents_starvation() {
+        let channel = PriorityChannel::with_fairness(3, 2);
+        for i in 0..6 {
+            channel.send_with_priority(('h', i), 0);
+        }
+        channel.send_with_priority(('m', 0), 1);
+        channel.send_with_priority(('l', 0), 2);
+
+        let received: Vec<_> = (0..8).map(|_| channel.try_receive().unwrap()).collect();
+        assert_eq!(
+            received,
+            [
+                ('h', 0),
+                ('h', 1),
+                // both lower lanes were skipped twice, the lowest one goes first
+                ('l', 0),
+                // the middle lane is still at the limit: a receive from a lower lane doesn't reset it
+                ('m', 0),
+                ('h', 2),
+                ('h', 3),
+                ('h', 4),
+                ('h', 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fairness_bounds_the_wait() {
+        const LANES: usize = 5;
+        const MAX_SKIPPED: usize = 2;
+        const MESSAGES: usize = 20;
+        let channel = PriorityChannel::with_fairness(LANES, MAX_SKIPPED);
+        // the highest lane never runs dry
+        channel.send_with_priority((0, 0), 0);
+        let mut sent = [1, 0, 0, 0, 0];
+        let mut next = [0; LANES];
+        // receives in a row that went to a higher lane while a lane had messages
+        let mut skipped = [0; LANES];
+        let mut step = 0;
+        while next[1..].iter().any(|&n| n < MESSAGES) {
+            // the lower lanes fill up at different times, so they reach the limit at different times
+            for (lane, sent) in sent.iter_mut().enumerate().skip(1) {
+                if step % lane == 0 && *sent < MESSAGES {
+                    channel.send_with_priority((lane, *sent), lane as u8);
+                    *sent += 1;
+                }
+            }
+            step += 1;
+
+            let (lane, i) = channel.try_receive().unwrap();
+            // still in order within a lane
+            assert_eq!(i, next[lane]);
+            next[lane] += 1;
+            if lane == 0 {
+                channel.send_with_priority((0, sent[0]), 0);
+                sent[0] += 1;
+            }
+            for l in 1..LANES {
+                if l == lane {
+                    skipped[l] = 0;
+                } else if l > lane && next[l] < sent[l] {
+                    skipped[l] += 1;
+                    assert!(
+                        skipped[l] <= MAX_SKIPPED,
+                        "lane {l} was skipped {} times",
+                        skipped[l]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let channel = PriorityChannel::new(2);
+        channel.send_batch_with_priority([3, 4, 5], 1);
+        channel.send_batch_with_priority([1, 2], 0);
+        let mut buffer = Vec::new();
+        assert_eq!(channel.drain_into(&mut buffer, 4), 4);
+        assert_eq!(channel.drain_into(&mut buffer, 4), 1);
+        assert_eq!(buffer, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_send_batch_panicking_iterator() {
+        let channel = PriorityChannel::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            channel.send_batch_with_priority(
+                (0..5).inspect(|&i| assert!(i < 3, "iterator panicked")),
+                1,
+            )
+        }));
+        assert!(result.is_err());
+        // nothing was sent, and the lock isn't poisoned
+        assert!(channel.is_empty());
+        // the iterator can use the channel itself
+        channel.send_batch_with_priority((0..2).map(|i| i + channel.len()), 0);
+        assert_eq!(channel.try_receive(), Some(0));
+        assert_eq!(channel.try_receive(), Some(1));
+    }
+
+    #[test]
+    fn test_blocking_receive_and_timeout() {
+        let channel = PriorityChannel::new(2);
+        assert_eq!(channel.try_receive(), None);
+        assert_eq!(channel.receive_timeout(Duration::from_millis(10)), None);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                channel.send_with_priority(1, 1);
+            });
+            assert_eq!(channel.receive(), 1);
+        });
+    }
+}
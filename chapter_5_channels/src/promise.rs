@@ -0,0 +1,258 @@
+use std::any::Any;
+use std::mem::MaybeUninit;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+
+use crate::sync::{thread, Arc, AtomicU8, LoadMut, Thread, UnsafeCell};
+
+const EMPTY: u8 = 0; // no outcome, nobody waiting
+const WAITING: u8 = 1; // the future registered its thread handle and is (about to be) parked
+const READY: u8 = 2; // an outcome is available
+
+/// What `std::thread::JoinHandle::join` returns for a panicked thread.
+pub type PanicPayload = Box<dyn Any + Send + 'static>;
+
+// Like send_recv_oneshot_channel, but the sender always leaves something behind:
+// the value, the panic that prevented it, or the fact that it was dropped without a value.
+// A `Promise` dropped while its thread is unwinding can't get hold of the actual panic payload,
+// so it sends a message saying so instead. `spawn_with_result` catches the panic itself
+// and passes on the real payload.
+//
+// Waiting works like movable_blocking_oneshot_channel.
+pub struct Promise<T> {
+    channel: Arc<Channel<T>>,
+    completed: bool,
+}
+
+pub struct Future<T> {
+    channel: Arc<Channel<T>>,
+}
+
+struct Channel<T> {
+    outcome: UnsafeCell<MaybeUninit<Outcome<T>>>,
+    // only written by the future before the EMPTY -> WAITING transition,
+    // only read by the promise after observing WAITING.
+    waiting_thread: UnsafeCell<Option<Thread>>,
+    state: AtomicU8,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+enum Outcome<T> {
+    Value(T),
+    Panicked(PanicPayload),
+    Dropped,
+}
+
+#[derive(Debug)]
+pub enum ReceiveError {
+    /// The thread that was supposed to fulfil the promise panicked.
+    Panicked(PanicPayload),
+    /// The promise was dropped without a value, but not because of a panic.
+    Dropped,
+}
+
+pub fn promise<T>() -> (Promise<T>, Future<T>) {
+    let channel = Arc::new(Channel {
+        outcome: UnsafeCell::new(MaybeUninit::uninit()),
+        waiting_thread: UnsafeCell::new(None),
+        state: AtomicU8::new(EMPTY),
+    });
+    (
+        Promise {
+            channel: channel.clone(),
+            completed: false,
+        },
+        Future { channel },
+    )
+}
+
+/// Runs `f` on a new thread.
+/// If `f` panics, the future gets the original panic payload.
+pub fn spawn_with_result<F, T>(f: F) -> Future<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (promise, future) = promise();
+    thread::spawn(move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => promise.set(value),
+        Err(payload) => promise.fail(payload),
+    });
+    future
+}
+
+impl<T> Promise<T> {
+    pub fn set(mut self, value: T) {
+        self.complete(Outcome::Value(value));
+    }
+
+    /// Hands a caught panic over to the future.
+    pub fn fail(mut self, payload: PanicPayload) {
+        self.complete(Outcome::Panicked(payload));
+    }
+
+    fn complete(&mut self, outcome: Outcome<T>) {
+        self.completed = true;
+        // Safety: only the promise writes the outcome, and only once.
+        self.channel
+            .outcome
+            .with_mut(|o| unsafe { (*o).write(outcome) });
+        // Release publishes the outcome,
+        // Acquire synchronises with the future's registration of its thread handle.
+        if self.channel.state.swap(READY, AcqRel) == WAITING {
+            // Safety: the future stored its handle before moving to WAITING
+            // and won't touch it again, so we're the only one accessing it.
+            let waiting_thread = self
+                .channel
+                .waiting_thread
+                .with_mut(|t| unsafe { (*t).take() });
+            if let Some(t) = waiting_thread {
+                t.unpark();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let outcome = if thread::panicking() {
+            let name = thread::current().name().unwrap_or("<unnamed>").to_owned();
+            let payload = format!("thread '{name}' panicked before fulfilling the promise");
+            Outcome::Panicked(Box::new(payload))
+        } else {
+            Outcome::Dropped
+        };
+        self.complete(outcome);
+    }
+}
+
+impl<T> Future<T> {
+    pub fn is_ready(&self) -> bool {
+        self.channel.state.load(Relaxed) == READY
+    }
+
+    /// Blocks until the promise is fulfilled, failed or dropped.
+    pub fn wait(self) -> Result<T, ReceiveError> {
+        if self.channel.state.load(Acquire) != READY {
+            // Safety: we're in the EMPTY state, so the promise won't read the handle
+            // until we publish it with the compare-exchange below.
+            self.channel
+                .waiting_thread
+                .with_mut(|t| unsafe { *t = Some(thread::current()) });
+            // Release makes the handle visible to the promise.
+            // If this fails, the outcome arrived in the meantime and we don't need to wait,
+            // but we still need Acquire to synchronise with the swap that made it READY.
+            if self
+                .channel
+                .state
+                .compare_exchange(EMPTY, WAITING, Release, Acquire)
+                .is_ok()
+            {
+                // park() might return spuriously, so check the state again after every wake up.
+                while self.channel.state.load(Acquire) != READY {
+                    thread::park();
+                }
+            }
+        }
+        // Reset the state so that dropping the channel doesn't drop the outcome again.
+        self.channel.state.store(EMPTY, Relaxed);
+        // Safety: the acquire loads above synchronise with the promise's swap.
+        let outcome = self
+            .channel
+            .outcome
+            .with(|o| unsafe { (*o).assume_init_read() });
+        match outcome {
+            Outcome::Value(value) => Ok(value),
+            Outcome::Panicked(payload) => Err(ReceiveError::Panicked(payload)),
+            Outcome::Dropped => Err(ReceiveError::Dropped),
+        }
+    }
+
+    /// Like `JoinHandle::join().unwrap()`, but with the original panic:
+    /// continues unwinding with the payload if the promise's thread panicked.
+    pub fn join(self) -> T {
+        match self.wait() {
+            Ok(value) => value,
+            Err(ReceiveError::Panicked(payload)) => panic::resume_unwind(payload),
+            Err(ReceiveError::Dropped) => panic!("promise dropped without a value"),
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        if self.state.load_mut() == READY {
+            self.outcome
+                .with_mut(|o| unsafe { (*o).assume_init_drop() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_value() {
+        let future = spawn_with_result(|| {
+            thread::sleep(Duration::from_millis(50));
+            42
+        });
+        assert_eq!(future.wait().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_panic_payload_is_propagated() {
+        let future = spawn_with_result(|| -> i32 { panic!("boom") });
+        match future.wait() {
+            Err(ReceiveError::Panicked(payload)) => {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        let future = spawn_with_result(|| -> i32 { panic!("boom") });
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| future.join())).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn test_promise_dropped_while_unwinding() {
+        let (promise, future) = promise::<i32>();
+        let t = thread::Builder::new()
+            .name("worker".to_owned())
+            .spawn(move || {
+                let _promise = promise;
+                panic!("boom");
+            })
+            .unwrap();
+        match future.wait() {
+            Err(ReceiveError::Panicked(payload)) => {
+                let message = payload.downcast_ref::<String>().unwrap();
+                assert!(message.contains("'worker' panicked"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(t.join().is_err());
+    }
+
+    #[test]
+    fn test_promise_dropped() {
+        let (promise, future) = promise::<i32>();
+        drop(promise);
+        assert!(future.is_ready());
+        assert!(matches!(future.wait(), Err(ReceiveError::Dropped)));
+
+        // dropping an unreceived value doesn't leak it
+        let value = Arc::new(());
+        let (promise, future) = super::promise();
+        promise.set(value.clone());
+        drop(future);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
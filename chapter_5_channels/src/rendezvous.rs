@@ -0,0 +1,390 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+
+// A channel without any buffer: a sender hands its message directly to a receiver,
+// and doesn't return until a receiver took it.
+// The mutex protects a single slot. A sender puts its message there and waits until
+// the `received` counter shows its message was taken, or takes it back on timeout/disconnection.
+//
+// Why not atomics and park/unpark, like the oneshot channels? Those have exactly one thread on each side,
+// so each side knows which thread to unpark. Here any number of senders and receivers can be waiting,
+// so we'd need a queue of waiting threads, and taking a message back on timeout would race with a receiver
+// taking it, which needs the slot and the counters to change together. That's a mutex and a condition
+// variable again (which park on the same futex underneath), like in naive_channel.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+    wakers: Wakers,
+    // a message only counts as sent once a receiver took it, so the depth is always zero
+    metrics: Metrics,
+}
+
+struct State<T> {
+    message: Option<T>,
+    sent: u64,     // number of messages put in the slot
+    received: u64, // number of messages taken from the slot
+    sender_count: usize,
+    receiver_count: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReceiveError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceiveTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryReceiveError {
+    Empty,
+    Disconnected,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            message: None,
+            sent: 0,
+            received: 0,
+            sender_count: 1,
+            receiver_count: 1,
+        }),
+        changed: Condvar::new(),
+        wakers: Wakers::new(),
+        metrics: Metrics::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+
+    /// Returns `None` if the deadline passed.
+    fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, State<T>>,
+        deadline: Option<Instant>,
+    ) -> Option<MutexGuard<'a, State<T>>> {
+        match deadline {
+            None => Some(self.changed.wait(state).unwrap()),
+            Some(deadline) => {
+                let timeout = deadline.checked_duration_since(Instant::now())?;
+                Some(self.changed.wait_timeout(state, timeout).unwrap().0)
+            }
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Blocks until a receiver took the message.
+    /// Gives the message back if all receivers are gone.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.send_until(message, None).map_err(|e| match e {
+            SendTimeoutError::Disconnected(message) => SendError(message),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+
+    /// Gives the message back if no receiver took it within `timeout`.
+    pub fn send_timeout(&self, message: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.send_until(message, Some(Instant::now() + timeout))
+    }
+
+    fn send_until(&self, message: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let timer = Timer::start();
+        let result = self.hand_over(message, deadline);
+        self.shared.metrics.sender_blocked(timer);
+        result
+    }
+
+    fn hand_over(&self, message: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut state = self.shared.lock();
+        // Wait for other senders to finish their handoff.
+        while state.message.is_some() && state.receiver_count > 0 {
+            match self.shared.wait(state, deadline) {
+                Some(s) => state = s,
+                None => return Err(SendTimeoutError::Timeout(message)),
+            }
+        }
+        if state.receiver_count == 0 {
+            return Err(SendTimeoutError::Disconnected(message));
+        }
+        state.message = Some(message);
+        state.sent += 1;
+        let ticket = state.sent;
+        self.shared.changed.notify_all();
+        self.shared.wakers.wake_all();
+
+        // Wait for a receiver to take it.
+        loop {
+            if state.received >= ticket {
+                return Ok(());
+            }
+            if state.receiver_count == 0 {
+                let message = state.take_back();
+                self.shared.changed.notify_all();
+                return Err(SendTimeoutError::Disconnected(message));
+            }
+            match self.shared.wait(state, deadline) {
+                Some(s) => state = s,
+                None => {
+                    // The deadline passed, but the message might have been taken just before.
+                    let mut s = self.shared.lock();
+                    if s.received >= ticket {
+                        return Ok(());
+                    }
+                    let message = s.take_back();
+                    self.shared.changed.notify_all();
+                    return Err(SendTimeoutError::Timeout(message));
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> State<T> {
+    // Only called by the sender whose message is still in the slot:
+    // no other sender can put a message there until it's empty again.
+    fn take_back(&mut self) -> T {
+        self.sent -= 1;
+        self.message.take().unwrap()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until a sender hands over a message.
+    /// Fails once all senders are gone.
+    pub fn receive(&self) -> Result<T, ReceiveError> {
+        self.receive_until(None).map_err(|_| ReceiveError)
+    }
+
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, ReceiveTimeoutError> {
+        self.receive_until(Some(Instant::now() + timeout))
+    }
+
+    /// Only succeeds if a sender is already waiting.
+    pub fn try_receive(&self) -> Result<T, TryReceiveError> {
+        let mut state = self.shared.lock();
+        match state.message.take() {
+            Some(message) => {
+                state.received += 1;
+                self.shared.changed.notify_all();
+                self.shared.metrics.sent(1);
+                self.shared.metrics.received(1);
+                Ok(message)
+            }
+            None if state.sender_count == 0 => Err(TryReceiveError::Disconnected),
+            None => Err(TryReceiveError::Empty),
+        }
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> Result<T, ReceiveTimeoutError> {
+        let timer = Timer::start();
+        let mut state = self.shared.lock();
+        let result = loop {
+            if let Some(message) = state.message.take() {
+                state.received += 1;
+                // wake up the sender waiting for the handoff (and the senders waiting for the slot)
+                self.shared.changed.notify_all();
+                self.shared.metrics.sent(1);
+                self.shared.metrics.received(1);
+                break Ok(message);
+            }
+            if state.sender_count == 0 {
+                break Err(ReceiveTimeoutError::Disconnected);
+            }
+            match self.shared.wait(state, deadline) {
+                Some(s) => state = s,
+                None => {
+                    self.shared.metrics.receiver_waited(timer);
+                    return Err(ReceiveTimeoutError::Timeout);
+                }
+            }
+        };
+        drop(state);
+        self.shared.metrics.receiver_waited(timer);
+        result
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        let state = self.shared.lock();
+        state.message.is_some() || state.sender_count == 0
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.shared.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.shared.wakers.unregister(waker);
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().sender_count += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receiver_count += 1;
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.sender_count -= 1;
+        if state.sender_count == 0 {
+            self.shared.changed.notify_all();
+            self.shared.wakers.wake_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_count -= 1;
+        if state.receiver_count == 0 {
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    #[test]
+    fn test_send_waits_for_receive() {
+        let (sender, receiver) = channel();
+        let receiving = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                receiving.store(true, Relaxed);
+                assert_eq!(receiver.receive(), Ok("hello"));
+            });
+            sender.send("hello").unwrap();
+            // send can only have returned after the receiver started receiving
+            assert!(receiving.load(Relaxed));
+        });
+    }
+
+    #[test]
+    fn test_multiple_senders_and_receivers() {
+        let (sender, receiver) = channel();
+        let total = thread::scope(|s| {
+            for i in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for j in 0..100 {
+                        sender.send(i * 100 + j).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            let receivers: Vec<_> = (0..2)
+                .map(|_| {
+                    let receiver = receiver.clone();
+                    s.spawn(move || {
+                        let mut sum = 0;
+                        while let Ok(n) = receiver.receive() {
+                            sum += n;
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            receivers
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .sum::<i32>()
+        });
+        assert_eq!(total, (0..400).sum());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            sender.send_timeout(1, Duration::from_millis(10)),
+            Err(SendTimeoutError::Timeout(1))
+        );
+        // the timed out message was taken back
+        assert_eq!(receiver.try_receive(), Err(TryReceiveError::Empty));
+        assert_eq!(
+            receiver.receive_timeout(Duration::from_millis(10)),
+            Err(ReceiveTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            let t = s.spawn(|| sender.send(1));
+            thread::sleep(Duration::from_millis(50));
+            drop(receiver);
+            assert_eq!(t.join().unwrap(), Err(SendError(1)));
+        });
+
+        let (sender, receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(receiver.receive(), Err(ReceiveError));
+    }
+}
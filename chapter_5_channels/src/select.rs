@@ -0,0 +1,312 @@
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize};
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// Something that can be waited on by `Select`.
+///
+/// `is_ready` must return true when receiving wouldn't block
+/// (a message is available, or the channel is disconnected).
+/// Registered wakers must be woken after anything that can make `is_ready` return true.
+pub trait Selectable {
+    fn is_ready(&self) -> bool;
+    fn register(&self, waker: &Waker);
+    fn unregister(&self, waker: &Waker);
+}
+
+static NEXT_WAKER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Handle to a thread blocked in `Select`.
+#[derive(Clone)]
+pub struct Waker {
+    id: u64,
+    thread: Thread,
+}
+
+impl Waker {
+    fn new() -> Self {
+        Waker {
+            id: NEXT_WAKER_ID.fetch_add(1, Relaxed),
+            thread: thread::current(),
+        }
+    }
+}
+
+/// The wakers registered on a channel.
+///
+/// Waking is on the send path of every channel, so it shouldn't lock anything when no one is selecting.
+/// That's why the number of wakers is also kept in an atomic, which has to be checked with care:
+/// the selecting thread registers and then checks `is_ready`,
+/// the sending thread makes the channel ready and then checks for wakers.
+/// The SeqCst fences make sure at least one of them sees what the other did,
+/// so the selecting thread can't miss the message and sleep forever.
+pub(crate) struct Wakers {
+    wakers: Mutex<Vec<Waker>>,
+    len: AtomicUsize,
+}
+
+impl Wakers {
+    pub(crate) const fn new() -> Self {
+        Wakers {
+            wakers: Mutex::new(Vec::new()),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        wakers.push(waker.clone());
+        self.len.store(wakers.len(), Relaxed);
+        drop(wakers);
+        fence(SeqCst);
+    }
+
+    pub(crate) fn unregister(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        wakers.retain(|w| w.id != waker.id);
+        self.len.store(wakers.len(), Relaxed);
+    }
+
+    pub(crate) fn wake_all(&self) {
+        fence(SeqCst);
+        self.wake_all_after_fence();
+    }
+
+    /// Like `wake_all`, for a caller that already ran a SeqCst fence after making the channel ready.
+    pub(crate) fn wake_all_after_fence(&self) {
+        if self.len.load(Relaxed) == 0 {
+            return;
+        }
+        for waker in self.wakers.lock().unwrap().iter() {
+            waker.thread.unpark();
+        }
+    }
+}
+
+impl Default for Wakers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Waits on several receivers at once.
+///
+/// Receivers are identified by the index returned from `add`.
+/// Selecting only tells which receiver is ready, receiving from it is up to the caller.
+/// If several receivers are ready, the one that was added first wins.
+///
+/// # Examples
+///
+/// ```
+/// use chapter_5_channels::naive_channel::Channel;
+/// use chapter_5_channels::select::Select;
+/// use chapter_5_channels::send_recv_oneshot_channel::channel;
+///
+/// let messages = Channel::<&str>::new();
+/// let (sender, receiver) = channel();
+/// std::thread::scope(|s| {
+///     s.spawn(|| sender.send("done"));
+///     let mut select = Select::new();
+///     let messages_index = select.add(&messages);
+///     let done_index = select.add(&receiver);
+///     let index = select.select();
+///     assert_eq!(index, done_index);
+///     assert_ne!(index, messages_index);
+/// });
+/// assert_eq!(receiver.receive(), "done");
+/// ```
+#[derive(Default)]
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Self {
+        Select {
+            receivers: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, receiver: &'a dyn Selectable) -> usize {
+        self.receivers.push(receiver);
+        self.receivers.len() - 1
+    }
+
+    pub fn try_select(&self) -> Option<usize> {
+        self.receivers.iter().position(|r| r.is_ready())
+    }
+
+    /// Blocks until one of the receivers is ready.
+    ///
+    /// Panics if no receivers were added, since that would block forever.
+    pub fn select(&self) -> usize {
+        assert!(!self.receivers.is_empty(), "nothing to select from");
+        self.select_until(None).unwrap()
+    }
+
+    pub fn select_timeout(&self, timeout: Duration) -> Option<usize> {
+        self.select_until(Some(Instant::now() + timeout))
+    }
+
+    fn select_until(&self, deadline: Option<Instant>) -> Option<usize> {
+        if let Some(index) = self.try_select() {
+            return Some(index);
+        }
+        let waker = Waker::new();
+        for receiver in &self.receivers {
+            receiver.register(&waker);
+        }
+        // park() might return spuriously, or because of an unrelated unpark(),
+        // so check all receivers again after every wake up.
+        let index = loop {
+            if let Some(index) = self.try_select() {
+                break Some(index);
+            }
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => thread::park_timeout(timeout),
+                    None => break None,
+                },
+            }
+        };
+        for receiver in &self.receivers {
+            receiver.unregister(&waker);
+        }
+        index
+    }
+}
+
+/// Blocks until one of the receivers is ready, then evaluates the expression of that receiver.
+///
+/// The receivers are only borrowed while waiting,
+/// so the expressions are free to consume them.
+///
+/// # Examples
+///
+/// ```
+/// use chapter_5_channels::naive_channel::Channel;
+/// use chapter_5_channels::select;
+/// use chapter_5_channels::send_recv_oneshot_channel::channel;
+///
+/// let messages = Channel::new();
+/// let (sender, receiver) = channel();
+/// sender.send(42);
+/// let received = select! {
+///     messages => messages.receive().unwrap(),
+///     receiver => receiver.receive(),
+/// };
+/// assert_eq!(received, 42);
+/// ```
+#[macro_export]
+macro_rules! select {
+    ($($receiver:expr => $body:expr),+ $(,)?) => {{
+        let index = {
+            let mut select = $crate::select::Select::new();
+            $( select.add(&$receiver); )+
+            select.select()
+        };
+        $crate::__select_branch!(index; 0usize; $($body),+)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_branch {
+    ($index:ident; $n:expr; $body:expr) => {{
+        debug_assert_eq!($index, $n);
+        $body
+    }};
+    ($index:ident; $n:expr; $body:expr, $($rest:expr),+) => {
+        if $index == $n {
+            $body
+        } else {
+            $crate::__select_branch!($index; $n + 1; $($rest),+)
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{broadcast, naive_channel, rendezvous, send_recv_oneshot_channel};
+
+    #[test]
+    fn test_select_wakes_up_on_send() {
+        let a = naive_channel::Channel::<i32>::new();
+        let b = naive_channel::Channel::new();
+        let (sender, receiver) = send_recv_oneshot_channel::channel::<&str>();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                b.send(7).unwrap();
+            });
+            let mut select = Select::new();
+            select.add(&a);
+            select.add(&b);
+            select.add(&receiver);
+            assert_eq!(select.select(), 1);
+        });
+        assert_eq!(b.receive(), Some(7));
+        drop(sender);
+    }
+
+    #[test]
+    fn test_select_wakes_up_on_dropped_oneshot_sender() {
+        let channel = naive_channel::Channel::<i32>::new();
+        let (sender, receiver) = send_recv_oneshot_channel::channel::<i32>();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                drop(sender);
+            });
+            let mut select = Select::new();
+            select.add(&channel);
+            select.add(&receiver);
+            assert_eq!(select.select(), 1);
+        });
+        assert!(!receiver.is_ready());
+        assert_eq!(
+            receiver.receive_if_sent(),
+            Err(send_recv_oneshot_channel::ReceiveError)
+        );
+    }
+
+    #[test]
+    fn test_try_select_and_timeout() {
+        let channel = naive_channel::Channel::new();
+        let (sender, receiver) = rendezvous::channel::<i32>();
+        let mut select = Select::new();
+        select.add(&channel);
+        select.add(&receiver);
+        assert_eq!(select.try_select(), None);
+        assert_eq!(select.select_timeout(Duration::from_millis(10)), None);
+        // a rendezvous receiver is ready once all senders are gone
+        drop(sender);
+        assert_eq!(select.try_select(), Some(1));
+        channel.send(1).unwrap();
+        assert_eq!(select.try_select(), Some(0));
+    }
+
+    #[test]
+    fn test_select_macro() {
+        let (sender, mut subscriber) = broadcast::channel(4);
+        let (rendezvous_sender, rendezvous_receiver) = rendezvous::channel();
+        let n = thread::scope(|s| {
+            s.spawn(move || rendezvous_sender.send(10).unwrap());
+            let mut total = 0;
+            for _ in 0..2 {
+                total += select! {
+                    subscriber => subscriber.receive().unwrap(),
+                    rendezvous_receiver => {
+                        sender.send(1).unwrap();
+                        rendezvous_receiver.receive().unwrap()
+                    },
+                };
+            }
+            total
+        });
+        assert_eq!(n, 11);
+    }
+}
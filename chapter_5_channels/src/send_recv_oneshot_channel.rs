@@ -1,9 +1,8 @@
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{
-    cell::UnsafeCell,
-    mem::MaybeUninit,
-    sync::{atomic::AtomicBool, Arc},
-};
+
+use crate::select::{Selectable, Waker, Wakers};
+use crate::sync::{Arc, AtomicBool, LoadMut, UnsafeCell};
 
 pub struct Sender<T> {
     channel: Arc<Channel<T>>,
@@ -17,14 +16,23 @@ pub struct Receiver<T> {
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    // set when the sender is gone, whether it sent something or not
+    disconnected: AtomicBool,
+    wakers: Wakers,
 }
 
+/// The sender was dropped without sending a message.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReceiveError;
+
 unsafe impl<T> Sync for Channel<T> where T: Send {}
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let a = Arc::new(Channel {
         message: UnsafeCell::new(MaybeUninit::uninit()),
         ready: AtomicBool::new(false),
+        disconnected: AtomicBool::new(false),
+        wakers: Wakers::new(),
     });
     (Sender { channel: a.clone() }, Receiver { channel: a })
 }
@@ -33,8 +41,19 @@ impl<T> Sender<T> {
     // takes self by value, owns self
     // can only be called once
     pub fn send(self, message: T) {
-        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel
+            .message
+            .with_mut(|m| unsafe { (*m).write(message) });
         self.channel.ready.store(true, Release);
+        self.channel.wakers.wake_all();
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release: whoever sees this also sees the message, if there was one.
+        self.channel.disconnected.store(true, Release);
+        self.channel.wakers.wake_all();
     }
 }
 
@@ -48,14 +67,50 @@ impl<T> Receiver<T> {
         if !self.channel.ready.swap(false, Acquire) {
             panic!("no message available!");
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        self.channel
+            .message
+            .with(|m| unsafe { (*m).assume_init_read() })
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.channel.disconnected.load(Relaxed)
+    }
+
+    /// Like `receive`, but gives an error instead of panicking if the sender was dropped without sending.
+    /// Still panics if the sender is around and hasn't sent anything yet.
+    pub fn receive_if_sent(self) -> Result<T, ReceiveError> {
+        // Checked first: once the sender is gone, a message it sent is visible below.
+        let disconnected = self.channel.disconnected.load(Acquire);
+        if self.channel.ready.load(Relaxed) {
+            Ok(self.receive())
+        } else if disconnected {
+            Err(ReceiveError)
+        } else {
+            panic!("no message available!");
+        }
+    }
+}
+
+// Ready once there's a message, or once there can't be one anymore.
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        Receiver::is_ready(self) || self.is_disconnected()
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.channel.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.channel.wakers.unregister(waker);
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() }
+        if self.ready.load_mut() {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
         }
     }
 }
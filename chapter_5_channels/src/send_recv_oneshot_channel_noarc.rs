@@ -1,5 +1,7 @@
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::AtomicBool};
+
+use crate::sync::{const_fn, AtomicBool, LoadMut, UnsafeCell};
 
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
@@ -17,10 +19,12 @@ pub struct Receiver<'a, T> {
 }
 
 impl<T> Channel<T> {
-    pub const fn new() -> Self {
-        Self {
-            message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
+    const_fn! {
+        pub fn new() -> Self {
+            Self {
+                message: UnsafeCell::new(MaybeUninit::uninit()),
+                ready: AtomicBool::new(false),
+            }
         }
     }
 
@@ -29,6 +33,7 @@ impl<T> Channel<T> {
     //     *self = Self::new();
     //     (Sender { channel: self }, Receiver { channel: self })
     // }
+    #[allow(mismatched_lifetime_syntaxes)] // the book's signature, which predates this lint
     pub fn split(&mut self) -> (Sender<T>, Receiver<T>) {
         *self = Self::new();
         (Sender { channel: self }, Receiver { channel: self })
@@ -37,7 +42,9 @@ impl<T> Channel<T> {
 
 impl<T> Sender<'_, T> {
     pub fn send(self, message: T) {
-        unsafe { (*self.channel.message.get()).write(message) };
+        self.channel
+            .message
+            .with_mut(|m| unsafe { (*m).write(message) });
         self.channel.ready.store(true, Release);
     }
 }
@@ -51,14 +58,17 @@ impl<T> Receiver<'_, T> {
         if !self.channel.ready.swap(false, Acquire) {
             panic!("no message available!");
         }
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+        self.channel
+            .message
+            .with(|m| unsafe { (*m).assume_init_read() })
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() }
+        if self.ready.load_mut() {
+            self.message
+                .with_mut(|m| unsafe { (*m).assume_init_drop() })
         }
     }
 }
@@ -0,0 +1,82 @@
+// The synchronisation primitives used by the channels that are model checked with loom
+// (see tests/loom.rs): std normally, loom's instrumented versions when built with `--cfg loom`.
+//
+// Loom's UnsafeCell only gives out pointers inside a closure, so it can track every access.
+// The std version below has the same interface, so the channels are written once for both.
+// Loom's constructors also aren't const, which is what `const_fn!` is for.
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, Thread},
+};
+
+#[cfg(not(loom))]
+pub(crate) use std::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, Thread},
+};
+
+#[cfg(not(loom))]
+#[derive(Debug)]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(crate) const fn new(data: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(data))
+    }
+
+    pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// Reads an atomic through `&mut`, like std's `get_mut`, which loom doesn't have.
+/// Nobody else can access it at the same time, so this doesn't need to be atomic.
+pub(crate) trait LoadMut<T> {
+    fn load_mut(&mut self) -> T;
+}
+
+macro_rules! impl_load_mut {
+    ($($atomic:ident: $t:ty),*) => {$(
+        impl LoadMut<$t> for $atomic {
+            #[cfg(not(loom))]
+            fn load_mut(&mut self) -> $t {
+                *self.get_mut()
+            }
+
+            #[cfg(loom)]
+            fn load_mut(&mut self) -> $t {
+                // Safety: we have exclusive access.
+                unsafe { self.unsync_load() }
+            }
+        }
+    )*};
+}
+
+impl_load_mut!(AtomicBool: bool, AtomicU8: u8, AtomicUsize: usize);
+
+/// A `const fn`, except under loom.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident() -> $ret:ty $body:block) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $name() -> $ret $body
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $name() -> $ret $body
+    };
+}
+
+pub(crate) use const_fn;
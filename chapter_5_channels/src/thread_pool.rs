@@ -0,0 +1,200 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::naive_channel::Channel;
+use crate::promise::{self, Future, ReceiveError};
+
+// A fixed number of worker threads taking jobs from a shared naive_channel.
+// `spawn` hands each job a promise, so its result (or its panic) ends up in the JoinHandle.
+//
+// Jobs run inside catch_unwind: a panicking job doesn't take its worker down with it.
+// Shutting down closes the channel, and the workers keep going until it's drained,
+// so every job submitted before that still runs.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+struct Shared {
+    jobs: Channel<Job>,
+    unfinished: Mutex<usize>, // queued or running
+    all_finished: Condvar,
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct JoinHandle<T> {
+    future: Future<T>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a thread pool needs at least one worker");
+        let shared = Arc::new(Shared {
+            jobs: Channel::new(),
+            unfinished: Mutex::new(0),
+            all_finished: Condvar::new(),
+        });
+        let workers = (0..size)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("pool-worker-{i}"))
+                    .spawn(move || shared.work())
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { shared, workers }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f` on one of the workers, ignoring its result.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        *self.shared.unfinished.lock().unwrap() += 1;
+        // Only closed when the pool is dropped, which can't happen while we borrow it.
+        if self.shared.jobs.send(Box::new(f)).is_err() {
+            unreachable!("job channel closed while the pool is alive");
+        }
+    }
+
+    /// Runs `f` on one of the workers, the handle receives its result or its panic.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (promise, future) = promise::promise();
+        self.execute(move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => promise.set(value),
+            Err(payload) => promise.fail(payload),
+        });
+        JoinHandle { future }
+    }
+
+    /// Blocks until every job submitted so far has finished.
+    /// The pool stays usable.
+    pub fn join(&self) {
+        let mut unfinished = self.shared.unfinished.lock().unwrap();
+        while *unfinished > 0 {
+            unfinished = self.shared.all_finished.wait(unfinished).unwrap();
+        }
+    }
+
+    /// Runs all submitted jobs, then stops the workers.
+    /// Dropping the pool does the same.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.jobs.close();
+        for worker in self.workers.drain(..) {
+            // jobs can't panic the worker, but don't panic in drop if it somehow did
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    fn work(&self) {
+        // `None` once the pool is shutting down and there are no jobs left
+        while let Some(job) = self.jobs.receive() {
+            // the panic was already reported by the panic hook,
+            // and `spawn` jobs pass it on themselves
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            let mut unfinished = self.unfinished.lock().unwrap();
+            *unfinished -= 1;
+            if *unfinished == 0 {
+                self.all_finished.notify_all();
+            }
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    pub fn is_finished(&self) -> bool {
+        self.future.is_ready()
+    }
+
+    /// Blocks until the job finished, failing with its panic payload if it panicked.
+    pub fn join(self) -> Result<T, ReceiveError> {
+        self.future.wait()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Duration;
+
+    #[test]
+    fn test_spawn_results() {
+        let pool = ThreadPool::new(4);
+        assert_eq!(pool.size(), 4);
+        let handles: Vec<_> = (0..20).map(|i| pool.spawn(move || i * i)).collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, (0..20).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_panic_doesnt_kill_worker() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("boom"));
+        let handle = pool.spawn(|| -> i32 { panic!("boom") });
+        match handle.join() {
+            Err(ReceiveError::Panicked(payload)) => {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        // the only worker is still around
+        assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_join_waits_for_all_jobs() {
+        let pool = ThreadPool::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let done = done.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                done.fetch_add(1, Relaxed);
+            });
+        }
+        pool.join();
+        assert_eq!(done.load(Relaxed), 10);
+        // still usable after joining
+        let handle = pool.spawn(|| "again");
+        assert_eq!(handle.join().unwrap(), "again");
+    }
+
+    #[test]
+    fn test_shutdown_runs_queued_jobs() {
+        let pool = ThreadPool::new(1);
+        let done = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                let done = done.clone();
+                pool.spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    done.fetch_add(1, Relaxed)
+                })
+            })
+            .collect();
+        pool.shutdown();
+        assert_eq!(done.load(Relaxed), 5);
+        assert!(handles.iter().all(|h| h.is_finished()));
+    }
+}
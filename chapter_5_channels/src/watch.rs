@@ -0,0 +1,300 @@
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::ChannelStats;
+use crate::metrics::{Metrics, Timer};
+use crate::select::{Selectable, Waker, Wakers};
+
+// Holds only the latest value: a send replaces it, so slow receivers skip the values in between.
+// The value lives behind a RwLock, so any number of receivers can look at it at the same time.
+// Every send bumps a version number, and every receiver remembers the version it last saw;
+// `changed` waits until the version moves past that.
+//
+// The version is only bumped while the value is write locked,
+// so a receiver holding a read lock always sees the version that belongs to the value.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    seen: u64, // version of the last value this receiver marked as seen
+}
+
+struct Shared<T> {
+    value: RwLock<T>,
+    state: Mutex<State>,
+    changed: Condvar,
+    wakers: Wakers,
+    // depth is one once something was sent (the initial value doesn't count),
+    // every receiver seeing a new value counts, senders block while the value is borrowed
+    metrics: Metrics,
+}
+
+struct State {
+    version: u64,
+    sender_alive: bool,
+    receiver_count: usize,
+}
+
+/// A read lock on the current value.
+/// Senders block until it's dropped, so don't hold on to it for long.
+pub struct Ref<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The sender is gone, the value won't change anymore.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReceiveError;
+
+pub fn channel<T>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        value: RwLock::new(initial),
+        state: Mutex::new(State {
+            version: 0,
+            sender_alive: true,
+            receiver_count: 1,
+        }),
+        changed: Condvar::new(),
+        wakers: Wakers::new(),
+        metrics: Metrics::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, seen: 0 },
+    )
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and wakes up everyone waiting in `changed`.
+    /// Gives the value back if there are no receivers.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let timer = Timer::start();
+        let mut current = self.shared.value.write().unwrap();
+        self.shared.metrics.sender_blocked(timer);
+        let mut state = self.shared.lock();
+        if state.receiver_count == 0 {
+            return Err(SendError(value));
+        }
+        let old = std::mem::replace(&mut *current, value);
+        if state.version > 0 {
+            self.shared.metrics.discarded(1);
+        }
+        self.shared.metrics.sent(1);
+        state.version += 1;
+        drop(state);
+        drop(current);
+        // the old value might take a while to drop, don't hold the locks for it
+        drop(old);
+        self.shared.changed.notify_all();
+        self.shared.wakers.wake_all();
+        Ok(())
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.value.read().unwrap(),
+        }
+    }
+
+    /// The new receiver considers the current value seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock();
+        state.receiver_count += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            seen: state.version,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.lock().receiver_count
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().sender_alive = false;
+        // wake up receivers waiting for a change that will never come
+        self.shared.changed.notify_all();
+        self.shared.wakers.wake_all();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it as seen.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.value.read().unwrap(),
+        }
+    }
+
+    /// The current value, marking it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let guard = self.shared.value.read().unwrap();
+        let version = self.shared.lock().version;
+        if version != self.seen {
+            self.shared.metrics.delivered(1);
+        }
+        self.seen = version;
+        Ref { guard }
+    }
+
+    /// Whether there's a value this receiver hasn't seen yet.
+    pub fn has_changed(&self) -> bool {
+        self.shared.lock().version != self.seen
+    }
+
+    /// Blocks until there's a value this receiver hasn't seen yet, and marks it as seen.
+    /// Fails once the sender is gone and there's nothing new to see.
+    pub fn changed(&mut self) -> Result<(), ReceiveError> {
+        let timer = Timer::start();
+        let mut state = self.shared.lock();
+        let result = loop {
+            if state.version != self.seen {
+                self.seen = state.version;
+                self.shared.metrics.delivered(1);
+                break Ok(());
+            }
+            if !state.sender_alive {
+                break Err(ReceiveError);
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        };
+        drop(state);
+        self.shared.metrics.receiver_waited(timer);
+        result
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> ChannelStats {
+        self.shared.metrics.snapshot()
+    }
+}
+
+// A clone has seen the same values as the original.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().receiver_count += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_count -= 1;
+    }
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        let state = self.shared.lock();
+        state.version != self.seen || !state.sender_alive
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.shared.wakers.register(waker);
+    }
+
+    fn unregister(&self, waker: &Waker) {
+        self.shared.wakers.unregister(waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_only_latest_value() {
+        let (sender, mut receiver) = channel("initial");
+        assert_eq!(*receiver.borrow(), "initial");
+        assert!(!receiver.has_changed());
+
+        sender.send("first").unwrap();
+        sender.send("second").unwrap();
+        assert!(receiver.has_changed());
+        // borrowing alone doesn't mark it as seen
+        assert_eq!(*receiver.borrow(), "second");
+        assert!(receiver.has_changed());
+        assert_eq!(*receiver.borrow_and_update(), "second");
+        assert!(!receiver.has_changed());
+        assert_eq!(*sender.borrow(), "second");
+    }
+
+    #[test]
+    fn test_changed_wakes_up() {
+        let (sender, mut receiver) = channel(0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                sender.send(1).unwrap();
+            });
+            assert_eq!(receiver.changed(), Ok(()));
+            assert_eq!(*receiver.borrow(), 1);
+        });
+        drop(sender);
+        assert_eq!(receiver.changed(), Err(ReceiveError));
+    }
+
+    #[test]
+    fn test_receivers_track_their_own_version() {
+        let (sender, mut a) = channel(0);
+        sender.send(1).unwrap();
+        let mut b = a.clone();
+        let mut c = sender.subscribe();
+        assert_eq!(sender.receiver_count(), 3);
+        assert!(b.has_changed());
+        assert!(!c.has_changed());
+
+        assert_eq!(a.changed(), Ok(()));
+        assert!(!a.has_changed());
+        assert!(b.has_changed());
+
+        sender.send(2).unwrap();
+        drop(sender);
+        // the last change is still there to be seen
+        assert_eq!(b.changed(), Ok(()));
+        assert_eq!(b.changed(), Err(ReceiveError));
+        assert_eq!(c.changed(), Ok(()));
+        assert_eq!(*c.borrow(), 2);
+    }
+
+    #[test]
+    fn test_send_without_receivers() {
+        let (sender, receiver) = channel(0);
+        drop(receiver);
+        assert_eq!(sender.send(1), Err(SendError(1)));
+        assert_eq!(*sender.borrow(), 0);
+    }
+}
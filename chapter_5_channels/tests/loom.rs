@@ -0,0 +1,319 @@
+// Model checks the oneshot channels, oneshot_slot and naive_channel with loom:
+// every test explores all possible interleavings (and memory orderings) of its threads.
+// Messages are loom Arcs, so a message that's never dropped fails the test as a leak.
+//
+// Run with `RUSTFLAGS="--cfg loom" cargo test --test loom --release`.
+#![cfg(loom)]
+
+use loom::sync::atomic::AtomicBool;
+use loom::sync::Arc;
+use loom::thread::{self, JoinHandle};
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+use chapter_5_channels::{
+    blocking_oneshot_channel, mem_opt_oneshot_channel, movable_blocking_oneshot_channel,
+    naive_channel, oneshot_channel, oneshot_slot, promise, send_recv_oneshot_channel,
+    send_recv_oneshot_channel_noarc,
+};
+
+/// Loom has no scoped threads, so channels that hand out borrowing halves are put on the heap
+/// for the duration of `f`, and dropped (along with whatever message is left in them) afterwards.
+/// `f` must join every thread it spawns.
+fn with_static<C: 'static, R>(channel: C, f: impl FnOnce(&'static mut C) -> R) -> R {
+    let channel = Box::into_raw(Box::new(channel));
+    // Safety: `f` joins its threads, so nothing uses the channel after it returns.
+    let result = f(unsafe { &mut *channel });
+    drop(unsafe { Box::from_raw(channel) });
+    result
+}
+
+/// Spawns `f` and returns a flag that's set once `f` returned.
+///
+/// Loom wakes up a thread blocked in `join` when it's unparked (std doesn't),
+/// so a thread that might still unpark us mustn't be joined before it's done with that.
+fn spawn_with_done_flag(f: impl FnOnce() + 'static) -> (JoinHandle<()>, Arc<AtomicBool>) {
+    let done = Arc::new(AtomicBool::new(false));
+    let t = thread::spawn({
+        let done = done.clone();
+        move || {
+            f();
+            done.store(true, Release);
+        }
+    });
+    (t, done)
+}
+
+fn join_when_done(t: JoinHandle<()>, done: &AtomicBool) {
+    while !done.load(Acquire) {
+        thread::yield_now();
+    }
+    t.join().unwrap();
+}
+
+#[test]
+fn oneshot_channel() {
+    loom::model(|| {
+        let channel = Arc::new(oneshot_channel::Channel::new());
+        let t = thread::spawn({
+            let channel = channel.clone();
+            move || channel.send(Arc::new(1))
+        });
+        if channel.is_ready() {
+            assert_eq!(*channel.receive(), 1);
+        }
+        // if it wasn't ready yet, the message is dropped along with the channel
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn mem_opt_oneshot_channel() {
+    loom::model(|| {
+        let channel = Arc::new(mem_opt_oneshot_channel::Channel::new());
+        let t = thread::spawn({
+            let channel = channel.clone();
+            move || channel.send(Arc::new(1))
+        });
+        if channel.is_ready() {
+            assert_eq!(*channel.receive(), 1);
+        }
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn send_recv_oneshot_channel() {
+    loom::model(|| {
+        let (sender, receiver) = send_recv_oneshot_channel::channel();
+        let t = thread::spawn(move || sender.send(Arc::new(1)));
+        if receiver.is_ready() {
+            assert_eq!(*receiver.receive(), 1);
+        }
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn send_recv_oneshot_channel_disconnected() {
+    loom::model(|| {
+        let (sender, receiver) = send_recv_oneshot_channel::channel();
+        let t = thread::spawn(move || sender.send(Arc::new(1)));
+        while !receiver.is_disconnected() {
+            thread::yield_now();
+        }
+        // a sender that's gone has sent everything it's going to send
+        assert_eq!(*receiver.receive_if_sent().unwrap(), 1);
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn send_recv_oneshot_channel_noarc() {
+    loom::model(|| {
+        with_static(send_recv_oneshot_channel_noarc::Channel::new(), |channel| {
+            let (sender, receiver) = channel.split();
+            let t = thread::spawn(move || sender.send(Arc::new(1)));
+            if receiver.is_ready() {
+                assert_eq!(*receiver.receive(), 1);
+            }
+            t.join().unwrap();
+        });
+    });
+}
+
+#[test]
+fn blocking_oneshot_channel() {
+    loom::model(|| {
+        with_static(blocking_oneshot_channel::Channel::new(), |channel| {
+            let (sender, receiver) = channel.split();
+            // the receiver isn't Send, so it has to receive on this thread
+            let (t, done) = spawn_with_done_flag(move || sender.send(Arc::new(1)));
+            assert_eq!(*receiver.receive(), 1);
+            join_when_done(t, &done);
+        });
+    });
+}
+
+#[test]
+fn blocking_oneshot_channel_unreceived() {
+    loom::model(|| {
+        with_static(blocking_oneshot_channel::Channel::new(), |channel| {
+            let (sender, _receiver) = channel.split();
+            let (t, done) = spawn_with_done_flag(move || sender.send(Arc::new(1)));
+            join_when_done(t, &done);
+        });
+    });
+}
+
+#[test]
+fn movable_blocking_oneshot_channel() {
+    loom::model(|| {
+        with_static(
+            movable_blocking_oneshot_channel::Channel::<Arc<i32>>::new(),
+            |channel| {
+                let (sender, receiver) = channel.split();
+                // the receiver waits on a thread other than the one that split the channel
+                let t = thread::spawn(move || *receiver.receive());
+                sender.send(Arc::new(1));
+                assert_eq!(t.join().unwrap(), 1);
+            },
+        );
+    });
+}
+
+#[test]
+fn movable_blocking_oneshot_channel_unreceived() {
+    loom::model(|| {
+        with_static(
+            movable_blocking_oneshot_channel::Channel::<Arc<i32>>::new(),
+            |channel| {
+                let (sender, receiver) = channel.split();
+                let t = thread::spawn(move || sender.send(Arc::new(1)));
+                if receiver.is_ready() {
+                    assert_eq!(*receiver.receive(), 1);
+                }
+                t.join().unwrap();
+            },
+        );
+    });
+}
+
+#[test]
+fn promise() {
+    loom::model(|| {
+        let (promise, future) = promise::promise::<Arc<i32>>();
+        let t = thread::spawn(move || *future.wait().unwrap());
+        promise.set(Arc::new(1));
+        assert_eq!(t.join().unwrap(), 1);
+    });
+}
+
+#[test]
+fn promise_dropped() {
+    loom::model(|| {
+        let (promise, future) = promise::promise::<Arc<i32>>();
+        let t = thread::spawn(move || future.wait().is_err());
+        drop(promise);
+        assert!(t.join().unwrap());
+    });
+}
+
+#[test]
+fn promise_unreceived() {
+    loom::model(|| {
+        let (promise, future) = promise::promise();
+        let t = thread::spawn(move || promise.set(Arc::new(1)));
+        drop(future);
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn oneshot_slot() {
+    loom::model(|| {
+        with_static(oneshot_slot::OneshotSlot::new(), |slot| {
+            let (sender, receiver) = slot.split().unwrap();
+            let t = thread::spawn(move || sender.send(Arc::new(1)).unwrap());
+            while !receiver.is_ready() {
+                thread::yield_now();
+            }
+            assert_eq!(*receiver.receive(), 1);
+            t.join().unwrap();
+            // the receiver recycled the slot
+            assert!(slot.split().is_some());
+        });
+    });
+}
+
+#[test]
+fn oneshot_slot_stale_sender() {
+    loom::model(|| {
+        with_static(oneshot_slot::OneshotSlot::new(), |slot| {
+            let (sender, receiver) = slot.split().unwrap();
+            // either sent before the slot is recycled, or given back
+            let t = thread::spawn(move || drop(sender.send(Arc::new(1))));
+            drop(receiver);
+            let (_, receiver) = slot.split().unwrap();
+            t.join().unwrap();
+            assert!(!receiver.is_ready());
+        });
+    });
+}
+
+#[test]
+fn slot_pool() {
+    loom::model(|| {
+        with_static(oneshot_slot::SlotPool::new(2), |pool| {
+            let pool = &*pool;
+            let threads: Vec<_> = (0..2)
+                .map(|i| {
+                    thread::spawn(move || {
+                        // there's a slot for each thread
+                        let (sender, receiver) = pool.acquire().unwrap();
+                        sender.send(Arc::new(i)).unwrap();
+                        assert_eq!(*receiver.receive(), i);
+                    })
+                })
+                .collect();
+            for t in threads {
+                t.join().unwrap();
+            }
+            // both slots made it back to the free list
+            let _a = pool.acquire().unwrap();
+            let _b = pool.acquire().unwrap();
+            assert!(pool.acquire().is_none());
+        });
+    });
+}
+
+#[test]
+fn naive_channel_two_senders() {
+    loom::model(|| {
+        let channel = Arc::new(naive_channel::Channel::new());
+        let senders: Vec<_> = (0..2)
+            .map(|i| {
+                let channel = channel.clone();
+                thread::spawn(move || channel.send(Arc::new(i)).unwrap())
+            })
+            .collect();
+        let mut received = [*channel.receive().unwrap(), *channel.receive().unwrap()];
+        received.sort();
+        assert_eq!(received, [0, 1]);
+        for t in senders {
+            t.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn naive_channel_close() {
+    loom::model(|| {
+        let channel = Arc::new(naive_channel::Channel::new());
+        let t = thread::spawn({
+            let channel = channel.clone();
+            move || {
+                channel.send(Arc::new(1)).unwrap();
+                channel.close();
+            }
+        });
+        let received: Vec<_> = channel.iter().map(|m| *m).collect();
+        assert_eq!(received, [1]);
+        t.join().unwrap();
+    });
+}
+
+#[test]
+fn naive_channel_unreceived() {
+    loom::model(|| {
+        let channel = Arc::new(naive_channel::Channel::new());
+        let t = thread::spawn({
+            let channel = channel.clone();
+            // fails if the channel was closed first, dropping the messages it gives back
+            move || drop(channel.send_batch([Arc::new(1), Arc::new(2)]))
+        });
+        channel.close();
+        t.join().unwrap();
+        // either everything was sent before closing, or it was all given back
+        assert!(channel.len() == 2 || channel.is_empty());
+    });
+}
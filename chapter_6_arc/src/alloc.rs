@@ -0,0 +1,69 @@
+use std::alloc::Layout;
+use std::fmt;
+use std::ptr::{self, NonNull};
+
+// A stable stand-in for the unstable `std::alloc::Allocator`, with the same shape
+// (and the one the allocator-api2 crate uses), so switching later is a rename.
+
+/// Something that hands out memory, like an arena.
+///
+/// # Safety
+///
+/// Memory returned by `allocate` must fit the layout, and stay valid until it's given to `deallocate`,
+/// on this allocator or a copy of it (or until the allocator and its copies are all gone).
+pub unsafe trait Allocator {
+    /// Allocates memory that fits `layout`, which might be larger than asked for.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// # Safety
+    ///
+    /// `ptr` must have come from `allocate` on this allocator with the same `layout`,
+    /// and not have been deallocated yet.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The allocator ran out of memory, or can't fit the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+/// The global allocator, which is what `Box` uses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = if layout.size() == 0 {
+            // The global allocator doesn't do zero sized allocations, any aligned address will do.
+            NonNull::new(ptr::without_provenance_mut(layout.align())).unwrap()
+        } else {
+            // Safety: the layout isn't zero sized.
+            NonNull::new(unsafe { std::alloc::alloc(layout) }).ok_or(AllocError)?
+        };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            std::alloc::dealloc(ptr.as_ptr(), layout);
+        }
+    }
+}
+
+// So an arena can be shared by reference.
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
@@ -1,19 +1,28 @@
+use std::alloc::{handle_alloc_error, Layout};
 use std::sync::atomic::fence;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
-use std::{ops::Deref, ptr::NonNull, sync::atomic::AtomicUsize};
+use std::{mem, mem::ManuallyDrop, ops::Deref, ptr, ptr::NonNull, sync::atomic::AtomicUsize};
 
-use crate::utils::non_null_from;
+use crate::alloc::{Allocator, Global};
+use crate::thin_arc::HeaderSlice;
+use crate::utils::{alloc_with_slice, alloc_with_slice_at};
 
-struct ArcData<T> {
+// repr(C) keeps `data` last, after the reference count, even when T is unsized,
+// so slices can be allocated as the reference count followed by the items.
+// The allocator is kept in the allocation too, so the last Arc can give the memory back to it.
+// (Global is zero sized, so that costs nothing by default.)
+#[repr(C)]
+struct ArcData<T: ?Sized, A: Allocator = Global> {
     ref_count: AtomicUsize,
+    alloc: A,
     data: T,
 }
 
-pub struct Arc<T> {
+pub struct Arc<T: ?Sized, A: Allocator = Global> {
     // raw pointer.
     // can't use Box: exclusive ownership, not shared
     // can't use reference: not borowing data owned by something else + can't represent the lifetime (until last clone of Arc is dropped).
-    ptr: NonNull<ArcData<T>>,
+    ptr: NonNull<ArcData<T, A>>,
 }
 
 // Sending an Arc<T> across threads results in a T object being shared, requiring T to be Sync.
@@ -21,24 +30,28 @@ pub struct Arc<T> {
 // effectively transferring it to the other thread, requiring T to be Send.
 // In other words, Arc<T> should be Send if and only if T is both Send and Sync.
 // The exact same holds for Sync, since a shared &Arc<T> can be cloned into a new Arc<T>.
-unsafe impl<T: Send + Sync> Send for Arc<T> {}
-unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+// The allocator is shared the same way, and used by whichever thread drops the last Arc.
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync> Send for Arc<T, A> {}
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send + Sync> Sync for Arc<T, A> {}
 
-impl<T> ArcData<T> {
-    pub fn new(data: T) -> Self {
+impl<T, A: Allocator> ArcData<T, A> {
+    pub fn new(data: T, alloc: A) -> Self {
         ArcData {
             ref_count: AtomicUsize::new(1),
+            alloc,
             data,
         }
     }
 }
-impl<T> Default for ArcData<T>
+impl<T, A> Default for ArcData<T, A>
 where
     T: Default,
+    A: Allocator + Default,
 {
     fn default() -> Self {
         ArcData {
             ref_count: AtomicUsize::new(1),
+            alloc: Default::default(),
             data: Default::default(),
         }
     }
@@ -46,12 +59,26 @@ where
 
 impl<T> Arc<T> {
     pub fn new(data: T) -> Self {
-        Arc {
-            ptr: non_null_from(ArcData::new(data)),
-        }
+        Arc::new_in(data, Global)
     }
+}
 
-    fn data(&self) -> &ArcData<T> {
+impl<T, A: Allocator> Arc<T, A> {
+    /// Allocates the data (and the reference count) with `alloc`, which frees it again after the last `Arc`.
+    pub fn new_in(data: T, alloc: A) -> Self {
+        let layout = Layout::new::<ArcData<T, A>>();
+        let ptr = match alloc.allocate(layout) {
+            Ok(mem) => mem.cast::<ArcData<T, A>>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        // Safety: the memory fits an ArcData.
+        unsafe { ptr.as_ptr().write(ArcData::new(data, alloc)) };
+        Arc { ptr }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Arc<T, A> {
+    fn data(&self) -> &ArcData<T, A> {
         // We know the pointer will always point to a valid ArcData<T> as long as the Arc object exists.
         // However, this is not something the compiler knows or checks for us,
         // so accessing the ArcData through the pointer requires unsafe code.
@@ -68,9 +95,173 @@ impl<T> Arc<T> {
             None
         }
     }
+
+    pub fn allocator(arc: &Self) -> &A {
+        &arc.data().alloc
+    }
+
+    /// A pointer to the data, which stays valid as long as there are `Arc`s.
+    pub fn as_ptr(arc: &Self) -> *const T {
+        // Derived from `ptr` rather than a reference to the data, so from_raw may reach back to the reference count.
+        unsafe { ptr::addr_of!((*arc.ptr.as_ptr()).data) }
+    }
+
+    /// Leaks the `Arc`, giving back a pointer to the data. Turn it back into an `Arc` with `from_raw`.
+    pub fn into_raw(arc: Self) -> *const T {
+        Arc::as_ptr(&ManuallyDrop::new(arc))
+    }
+
+    /// Like `from_raw`, for an `Arc` with any allocator.
+    /// (Unlike std's, this doesn't take the allocator: it's in the allocation.)
+    ///
+    /// # Safety
+    ///
+    /// Like `from_raw`, with `ptr` from `Arc::<T, A>::into_raw`.
+    pub unsafe fn from_raw_in(ptr: *const T) -> Self {
+        // The data is still alive, so its alignment (which is in the vtable for trait objects) can be read.
+        // Not last_field_offset: ArcData<(), A> is padded up to a usize, the data starts right after the allocator.
+        let end_of_alloc = mem::offset_of!(ArcData<(), A>, alloc) + mem::size_of::<A>();
+        let offset = end_of_alloc.next_multiple_of(mem::align_of_val(&*ptr));
+        Arc {
+            ptr: NonNull::new_unchecked(ptr.byte_sub(offset) as *mut ArcData<T, A>),
+        }
+    }
+
+    /// Like `increment_strong_count`, for an `Arc` with any allocator.
+    ///
+    /// # Safety
+    ///
+    /// Like `increment_strong_count`, with `ptr` from `Arc::<T, A>::into_raw`.
+    pub unsafe fn increment_strong_count_in(ptr: *const T) {
+        let arc = ManuallyDrop::new(Self::from_raw_in(ptr));
+        let _clone: ManuallyDrop<Self> = arc.clone();
+    }
+
+    /// Like `decrement_strong_count`, for an `Arc` with any allocator.
+    ///
+    /// # Safety
+    ///
+    /// Like `decrement_strong_count`, with `ptr` from `Arc::<T, A>::into_raw`.
+    pub unsafe fn decrement_strong_count_in(ptr: *const T) {
+        drop(Self::from_raw_in(ptr));
+    }
+
+    /// Turns this into an `Arc<U>` pointing at the same data, through a pointer `coerce`d to `U`.
+    /// Use the `unsize!` macro instead, which can only coerce.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return the pointer it's given, only with `T` unsized to `U`.
+    pub unsafe fn unsize<U: ?Sized>(
+        arc: Self,
+        coerce: impl FnOnce(*const T) -> *const U,
+    ) -> Arc<U, A> {
+        let data = Arc::into_raw(arc);
+        let unsized_data = coerce(data);
+        assert_eq!(data as *const u8, unsized_data as *const u8);
+        Arc::from_raw_in(unsized_data)
+    }
+}
+
+// Separate from the above so `Arc::from_raw(ptr)` doesn't need to know the allocator.
+impl<T: ?Sized> Arc<T> {
+    /// Takes back an `Arc` leaked by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T>::into_raw` (or `as_ptr`, with the count incremented for it),
+    /// and be used to take back the `Arc` only once.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        Self::from_raw_in(ptr)
+    }
+
+    /// Clones the `Arc` behind a pointer from `into_raw`, leaving the pointer in place.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T>::into_raw`, and the `Arc` mustn't have been taken back yet.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        Self::increment_strong_count_in(ptr)
+    }
+
+    /// Drops the `Arc` behind a pointer from `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// Like `from_raw`: `ptr` must come from `Arc::<T>::into_raw`, and can't be used again afterwards.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        Self::decrement_strong_count_in(ptr)
+    }
+}
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(items: Vec<T>) -> Self {
+        let header = ArcData {
+            ref_count: AtomicUsize::new(1),
+            alloc: Global,
+            data: (),
+        };
+        // Safety: the slice is allocated right after the fields of the header, like in ArcData<[T]>.
+        Arc {
+            ptr: unsafe {
+                NonNull::new_unchecked(alloc_with_slice(header, items).as_ptr() as *mut ArcData<[T]>)
+            },
+        }
+    }
+}
+
+impl<H, T> Arc<HeaderSlice<H, [T]>> {
+    /// The header and the items in a single allocation, after the reference count.
+    /// See `ThinArc` for the same with a thin pointer.
+    pub fn from_header_and_iter(header: H, items: impl IntoIterator<Item = T>) -> Self {
+        let items: Vec<T> = items.into_iter().collect();
+        let header = ArcData {
+            ref_count: AtomicUsize::new(1),
+            alloc: Global,
+            data: HeaderSlice::<H, [T]>::header(header, items.len()),
+        };
+        let offset = mem::offset_of!(ArcData<HeaderSlice<H, [T; 0]>>, data.slice);
+        // Safety: the slice is allocated where the last field of the header is, like in ArcData<HeaderSlice<H, [T]>>,
+        // and the pointer keeps its length as metadata when cast.
+        Arc {
+            ptr: unsafe {
+                NonNull::new_unchecked(alloc_with_slice_at(header, offset, items).as_ptr()
+                    as *mut ArcData<HeaderSlice<H, [T]>>)
+            },
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(items: &[T]) -> Self {
+        Arc::from(items.to_vec())
+    }
+}
+
+impl<T> FromIterator<T> for Arc<[T]> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Arc::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        let bytes: Arc<[u8]> = Arc::from(s.into_bytes());
+        let ptr = ManuallyDrop::new(bytes).ptr;
+        // Safety: the bytes are valid UTF-8, and str has the same layout as [u8].
+        Arc {
+            ptr: unsafe { NonNull::new_unchecked(ptr.as_ptr() as *mut ArcData<str>) },
+        }
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        Arc::from(s.to_owned())
+    }
 }
 
-impl<T> Deref for Arc<T> {
+impl<T: ?Sized, A: Allocator> Deref for Arc<T, A> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -78,7 +269,7 @@ impl<T> Deref for Arc<T> {
     }
 }
 
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized, A: Allocator> Clone for Arc<T, A> {
     fn clone(&self) -> Self {
         // increment atomic reference count
         // abort process if we get close to an overflow
@@ -89,7 +280,7 @@ impl<T> Clone for Arc<T> {
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized, A: Allocator> Drop for Arc<T, A> {
     fn drop(&mut self) {
         // every single drop of one of the former Arc clones must have happened before the final drop.
         // So, the final fetch_sub must establish a happens-before relationship with every previous fetch_sub operation,
@@ -98,10 +289,14 @@ impl<T> Drop for Arc<T> {
         // decrement atomic reference counter
         if self.data().ref_count.fetch_sub(1, Release) == 1 {
             fence(Acquire);
-            // if it's the last Arc: reclaim ownership of the pointer using a Box
-            // and drop it
+            // if it's the last Arc: drop the data, and give the memory back to the allocator,
+            // which we move out first: it's in the memory we're giving back.
+            let ptr = self.ptr.as_ptr();
             unsafe {
-                drop(Box::from_raw(self.ptr.as_ptr()));
+                let layout = Layout::for_value(&*ptr);
+                ptr::drop_in_place(ptr::addr_of_mut!((*ptr).data));
+                let alloc = ptr::read(ptr::addr_of!((*ptr).alloc));
+                alloc.deallocate(self.ptr.cast(), layout);
             }
         }
     }
@@ -150,4 +345,256 @@ pub mod test {
         // the object should've been dropped.
         assert_eq!(NUM_DROPS.load(Relaxed), 1);
     }
+
+    #[test]
+    fn test_slice_drops_every_item() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let x: Arc<[DetectDrop]> = (0..5).map(DetectDrop).collect();
+        let y = x.clone();
+        assert_eq!(x.len(), 5);
+        assert_eq!(y.iter().map(|d| d.0).sum::<usize>(), 10);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 5);
+
+        // cloned from a slice, the original items are still around
+        let items = vec![DetectDrop(0), DetectDrop(1)];
+        let x = Arc::from(&items[..]);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 7);
+        drop(items);
+        assert_eq!(NUM_DROPS.load(Relaxed), 9);
+
+        let empty: Arc<[DetectDrop]> = Arc::from(Vec::new());
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_slice_alignment() {
+        // more aligned than the reference count
+        #[repr(align(32))]
+        struct Aligned(u8);
+
+        let x: Arc<[Aligned]> = Arc::from(vec![Aligned(1), Aligned(2), Aligned(3)]);
+        assert_eq!(x.as_ptr() as usize % 32, 0);
+        assert_eq!(x.iter().map(|a| a.0).collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_str() {
+        let x: Arc<str> = Arc::from("hello");
+        let y = x.clone();
+        assert_eq!(&*y, "hello");
+        let z = Arc::from(String::from("world"));
+        assert_eq!(format!("{} {}", &*x, &*z), "hello world");
+    }
+
+    #[test]
+    fn test_unsize() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let x = Arc::new(("hello", DetectDrop));
+        let y: Arc<dyn std::any::Any + Send + Sync> =
+            crate::unsize!(x.clone() => Arc<dyn std::any::Any + Send + Sync>);
+        assert_eq!(y.downcast_ref::<(&str, DetectDrop)>().unwrap().0, "hello");
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        let array = Arc::new([DetectDrop, DetectDrop]);
+        let slice = crate::unsize!(array => Arc<[DetectDrop]>);
+        assert_eq!(slice.len(), 2);
+        drop(slice);
+        assert_eq!(NUM_DROPS.load(Relaxed), 3);
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(&'static str);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let x = Arc::new(DetectDrop("hello"));
+        let ptr = Arc::into_raw(x);
+        // points at the data itself
+        assert_eq!(unsafe { (*ptr).0 }, "hello");
+        unsafe { Arc::increment_strong_count(ptr) };
+        let x = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(Arc::as_ptr(&x), ptr);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        unsafe { Arc::decrement_strong_count(ptr) };
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        // unsized, with the data more aligned than the reference count
+        let x: Arc<[u128]> = Arc::from(vec![1, 2, 3]);
+        let ptr = Arc::into_raw(x);
+        assert_eq!(unsafe { &*ptr }, [1, 2, 3]);
+        let x = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(x.len(), 3);
+
+        let x: Arc<str> = Arc::from("hello");
+        let x = unsafe { Arc::from_raw(Arc::into_raw(x)) };
+        assert_eq!(&*x, "hello");
+    }
+
+    /// Hands out memory from the global allocator, counting how much is still out.
+    #[derive(Default)]
+    struct CountingAlloc {
+        allocated: AtomicUsize,
+        deallocated: AtomicUsize,
+        bytes: AtomicUsize,
+    }
+
+    unsafe impl Allocator for CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, crate::alloc::AllocError> {
+            self.allocated.fetch_add(1, Relaxed);
+            self.bytes.fetch_add(layout.size(), Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.deallocated.fetch_add(1, Relaxed);
+            self.bytes.fetch_sub(layout.size(), Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn test_allocator() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(u64);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let alloc = CountingAlloc::default();
+        let x = Arc::new_in(DetectDrop(1), &alloc);
+        let y = Arc::new_in(DetectDrop(2), &alloc);
+        assert_eq!(alloc.allocated.load(Relaxed), 2);
+        assert!(ptr::eq(*Arc::allocator(&x), &alloc));
+
+        // shared with other threads, the last one to drop its clone frees it
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let x = x.clone();
+                s.spawn(move || assert_eq!(x.0, 1));
+            }
+        });
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert_eq!(alloc.deallocated.load(Relaxed), 1);
+
+        // the raw pointer finds its way back to the allocator
+        let ptr = Arc::into_raw(y);
+        let y = unsafe { Arc::<_, &CountingAlloc>::from_raw_in(ptr) };
+        let y = crate::unsize!(y => Arc<dyn std::any::Any + Send + Sync, &CountingAlloc>);
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 2);
+        assert_eq!(alloc.allocated.load(Relaxed), 2);
+        assert_eq!(alloc.deallocated.load(Relaxed), 2);
+        assert_eq!(alloc.bytes.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_allocator_by_value() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        // an allocator that's dropped along with the last Arc, after freeing the memory
+        struct DropAlloc(std::sync::Arc<CountingAlloc>);
+
+        unsafe impl Allocator for DropAlloc {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, crate::alloc::AllocError> {
+                self.0.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.deallocate(ptr, layout)
+            }
+        }
+
+        impl Drop for DropAlloc {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let counts = std::sync::Arc::new(CountingAlloc::default());
+        let x = Arc::new_in(String::from("hello"), DropAlloc(counts.clone()));
+        let y = x.clone();
+        drop(x);
+        assert_eq!(*y, "hello");
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert_eq!(counts.allocated.load(Relaxed), 1);
+        assert_eq!(counts.deallocated.load(Relaxed), 1);
+        assert_eq!(counts.bytes.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_allocator_raw_round_trip() {
+        // one byte: the data isn't at the padded size of the header, but right after the allocator
+        #[derive(Clone, Copy)]
+        struct Tagged(u8);
+
+        unsafe impl Allocator for Tagged {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, crate::alloc::AllocError> {
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+
+        let x = Arc::new_in(7u8, Tagged(3));
+        let ptr = Arc::into_raw(x);
+        assert_eq!(unsafe { *ptr }, 7);
+        unsafe { Arc::<u8, Tagged>::increment_strong_count_in(ptr) };
+        let x = unsafe { Arc::<u8, Tagged>::from_raw_in(ptr) };
+        assert_eq!((*x, Arc::allocator(&x).0), (7, 3));
+        assert_eq!(Arc::as_ptr(&x), ptr);
+        drop(x);
+        unsafe { Arc::<u8, Tagged>::decrement_strong_count_in(ptr) };
+
+        // more aligned data, and unsized
+        let x = Arc::new_in([1u64, 2, 3], Tagged(5));
+        let y = crate::unsize!(x.clone() => Arc<[u64], Tagged>);
+        assert_eq!((&*y, Arc::allocator(&y).0), (&[1, 2, 3][..], 5));
+        let y = unsafe { Arc::<[u64], Tagged>::from_raw_in(Arc::into_raw(y)) };
+        assert_eq!(*y, [1, 2, 3]);
+        assert_eq!(Arc::as_ptr(&y) as *const u64, Arc::as_ptr(&x) as *const u64);
+    }
 }
@@ -1,13 +1,17 @@
+use std::borrow::Borrow;
 use std::cell::UnsafeCell;
-use std::mem::ManuallyDrop;
-use std::ops::Deref;
-use std::ptr::NonNull;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut};
+use std::ptr::{self, NonNull};
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use std::sync::atomic::{fence, AtomicUsize};
 
-use crate::utils::non_null_from;
+use crate::utils::{alloc_with_slice, last_field_offset, non_null_from};
 
-pub struct Arc<T> {
+pub struct Arc<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
@@ -18,6 +22,58 @@ impl<T> Arc<T> {
         }
     }
 
+    /// Creates the data with a `Weak` to itself, for self-referential structures.
+    /// The `Weak` can be cloned and stored, but not upgraded until this returns.
+    pub fn new_cyclic(data_fn: impl FnOnce(&Weak<T>) -> T) -> Arc<T> {
+        // No `Arc`s yet, so upgrading fails. The allocation's count is for the `Weak` given to `data_fn`.
+        let weak = Weak {
+            ptr: ArcData::init_counters(Box::new_uninit(), 0),
+        };
+        // If this panics, dropping `weak` frees the allocation, without touching the data.
+        let data = data_fn(&weak);
+        // Safety: there are no `Arc`s yet, so nothing else accesses the data.
+        // Through raw pointers, since the data isn't valid until it's written.
+        unsafe {
+            let ptr = weak.ptr.as_ptr();
+            UnsafeCell::raw_get(ptr::addr_of!((*ptr).data)).write(ManuallyDrop::new(data));
+            // Release matches the Acquire in upgrade, which makes the data visible to upgraded pointers.
+            (*ptr).data_ref_count.store(1, Release);
+        }
+        // The `Weak` given to `data_fn` becomes the implicit weak pointer that represents all `Arc`s.
+        let weak = ManuallyDrop::new(weak);
+        Arc { ptr: weak.ptr }
+    }
+
+    /// Allocates an `Arc` for data to be written later, like with `get_mut`.
+    /// `UniqueArc::new_uninit` doesn't need the `get_mut(..).unwrap()`.
+    pub fn new_uninit() -> Arc<MaybeUninit<T>> {
+        Arc {
+            ptr: ArcData::init_counters(Box::new_uninit(), 1),
+        }
+    }
+
+    /// Like `new_uninit`, with the data zeroed.
+    pub fn new_zeroed() -> Arc<MaybeUninit<T>> {
+        Arc {
+            ptr: ArcData::init_counters(Box::new_zeroed(), 1),
+        }
+    }
+}
+
+impl<T> Arc<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// The data must have been initialised.
+    pub unsafe fn assume_init(self) -> Arc<T> {
+        // MaybeUninit<T> has the same layout as T.
+        let arc = ManuallyDrop::new(self);
+        Arc {
+            ptr: arc.ptr.cast::<ArcData<T>>(),
+        }
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
@@ -68,9 +124,167 @@ impl<T> Arc<T> {
             return Weak { ptr: arc.ptr };
         }
     }
+
+    pub fn strong_count(arc: &Self) -> usize {
+        arc.data().data_ref_count.load(Relaxed)
+    }
+
+    /// The number of `Weak`s, not counting the one that represents all `Arc`s.
+    pub fn weak_count(arc: &Self) -> usize {
+        match arc.data().alloc_ref_count.load(Relaxed) {
+            // get_mut has it locked, which means there were no `Weak`s.
+            usize::MAX => 0,
+            n => n - 1,
+        }
+    }
+
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        // Only the addresses: the same data can be behind different vtables.
+        ptr::addr_eq(a.ptr.as_ptr(), b.ptr.as_ptr())
+    }
+
+    /// A pointer to the data, which stays valid as long as there are `Arc`s.
+    pub fn as_ptr(arc: &Self) -> *const T {
+        // Derived from `ptr` rather than a reference to the data, so from_raw may reach back to the counters.
+        // ManuallyDrop<T> has the same layout as T.
+        unsafe { ptr::addr_of!((*arc.ptr.as_ptr()).data) as *const T }
+    }
+
+    /// Leaks the `Arc`, giving back a pointer to the data. Turn it back into an `Arc` with `from_raw`.
+    pub fn into_raw(arc: Self) -> *const T {
+        Arc::as_ptr(&ManuallyDrop::new(arc))
+    }
+
+    /// Takes back an `Arc` leaked by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T>::into_raw` (or `as_ptr`, with the count incremented for it),
+    /// and be used to take back the `Arc` only once.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        // The data is still alive, so its alignment (which is in the vtable for trait objects) can be read.
+        let offset = last_field_offset::<ArcData<()>>(mem::align_of_val(&*ptr));
+        Arc {
+            ptr: NonNull::new_unchecked(ptr.byte_sub(offset) as *mut ArcData<T>),
+        }
+    }
+
+    /// Clones the `Arc` behind a pointer from `into_raw`, leaving the pointer in place.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Arc::<T>::into_raw`, and the `Arc` mustn't have been taken back yet.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let arc = ManuallyDrop::new(Arc::from_raw(ptr));
+        let _clone: ManuallyDrop<Self> = arc.clone();
+    }
+
+    /// Drops the `Arc` behind a pointer from `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// Like `from_raw`: `ptr` must come from `Arc::<T>::into_raw`, and can't be used again afterwards.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Arc::from_raw(ptr));
+    }
+
+    /// Turns this into an `Arc<U>` pointing at the same data, through a pointer `coerce`d to `U`.
+    /// Use the `unsize!` macro instead, which can only coerce.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return the pointer it's given, only with `T` unsized to `U`.
+    pub unsafe fn unsize<U: ?Sized>(
+        arc: Self,
+        coerce: impl FnOnce(*const T) -> *const U,
+    ) -> Arc<U> {
+        let data = Arc::into_raw(arc);
+        let unsized_data = coerce(data);
+        assert_eq!(data as *const u8, unsized_data as *const u8);
+        Arc::from_raw(unsized_data)
+    }
+}
+
+impl<T> Arc<T> {
+    /// Gives back the data if this is the only `Arc`, or the `Arc` itself if it isn't.
+    pub fn try_unwrap(arc: Self) -> Result<T, Self> {
+        if arc
+            .data()
+            .data_ref_count
+            .compare_exchange(1, 0, Relaxed, Relaxed)
+            .is_err()
+        {
+            return Err(arc);
+        }
+        // Acquire to match Arc::drop's Release decrement, like in Arc::drop.
+        fence(Acquire);
+        let arc = ManuallyDrop::new(arc);
+        // Safety: the data reference counter is zero, so we're the only one with access,
+        // and nothing will read or drop the data anymore.
+        let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.get()) };
+        drop(Weak { ptr: arc.ptr });
+        Ok(data)
+    }
+
+    /// Gives back the data if this is the last `Arc`, dropping the `Arc` either way.
+    /// Unlike `try_unwrap(arc).ok()`, exactly one of several threads
+    /// calling this on the last `Arc`s gets the data.
+    pub fn into_inner(arc: Self) -> Option<T> {
+        let arc = ManuallyDrop::new(arc);
+        if arc.data().data_ref_count.fetch_sub(1, Release) != 1 {
+            return None;
+        }
+        fence(Acquire);
+        // Safety: same as in try_unwrap.
+        let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.get()) };
+        drop(Weak { ptr: arc.ptr });
+        Some(data)
+    }
+
+    /// Like `try_unwrap`, but clones the data if there are other `Arc`s.
+    pub fn unwrap_or_clone(arc: Self) -> T
+    where
+        T: Clone,
+    {
+        Arc::try_unwrap(arc).unwrap_or_else(|arc| (*arc).clone())
+    }
+
+    /// Clone-on-write: clones the data into a new allocation if there are other `Arc`s.
+    /// If there are only `Weak`s, the data is moved to a new allocation instead,
+    /// and the `Weak`s can no longer be upgraded.
+    pub fn make_mut(arc: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        // Acquire to match Arc::drop's Release decrement,
+        // so the data is no longer accessed by the `Arc`s that were dropped.
+        if arc
+            .data()
+            .data_ref_count
+            .compare_exchange(1, 0, Acquire, Relaxed)
+            .is_err()
+        {
+            // Other `Arc`s share the data.
+            *arc = Arc::new((**arc).clone());
+        } else if arc.data().alloc_ref_count.load(Relaxed) != 1 {
+            // We were the only `Arc`, but there are `Weak`s.
+            // The data reference counter is now zero, so they can't upgrade anymore.
+            // Drops the implicit weak pointer that represented all `Arc`s at the end of the block.
+            let _weak = Weak { ptr: arc.ptr };
+            // Safety: nothing else can access the data anymore.
+            let data = unsafe { ManuallyDrop::take(&mut *arc.data().data.get()) };
+            // Safety: the old `Arc` mustn't be dropped, it's taken care of by `_weak`.
+            unsafe { ptr::write(arc, Arc::new(data)) };
+        } else {
+            // We were the only pointer of any kind, put the counter back.
+            arc.data().data_ref_count.store(1, Release);
+        }
+        // Safety: the `Arc` is unique now.
+        unsafe { &mut *arc.data().data.get() }
+    }
 }
 
-impl<T> Deref for Arc<T> {
+impl<T: ?Sized> Deref for Arc<T> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -80,7 +294,7 @@ impl<T> Deref for Arc<T> {
     }
 }
 
-impl<T> Clone for Arc<T> {
+impl<T: ?Sized> Clone for Arc<T> {
     fn clone(&self) -> Self {
         if self.data().data_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
             std::process::abort();
@@ -89,7 +303,7 @@ impl<T> Clone for Arc<T> {
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: ?Sized> Drop for Arc<T> {
     fn drop(&mut self) {
         if self.data().data_ref_count.fetch_sub(1, Release) == 1 {
             fence(Acquire);
@@ -105,19 +319,136 @@ impl<T> Drop for Arc<T> {
     }
 }
 
-unsafe impl<T: Sync + Send> Send for Arc<T> {}
-unsafe impl<T: Sync + Send> Sync for Arc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for Arc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Arc<T> {}
 
-pub struct Weak<T> {
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// Comparisons look at the data, not at the pointers, use `ptr_eq` for that.
+impl<T: ?Sized + PartialEq> PartialEq for Arc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Arc<T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Arc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Arc<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for Arc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized> Borrow<T> for Arc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Arc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Arc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Arc<T> {
+    fn from(data: T) -> Self {
+        Arc::new(data)
+    }
+}
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(items: Vec<T>) -> Self {
+        let header = ArcData {
+            data_ref_count: AtomicUsize::new(1),
+            alloc_ref_count: AtomicUsize::new(1),
+            data: UnsafeCell::new(ManuallyDrop::new(())),
+        };
+        // Safety: the slice is allocated right after the fields of the header, like in ArcData<[T]>,
+        // and UnsafeCell<ManuallyDrop<[T]>> has the same layout as [T].
+        Arc {
+            ptr: unsafe {
+                NonNull::new_unchecked(alloc_with_slice(header, items).as_ptr() as *mut ArcData<[T]>)
+            },
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(items: &[T]) -> Self {
+        Arc::from(items.to_vec())
+    }
+}
+
+impl<T> FromIterator<T> for Arc<[T]> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Arc::from(iter.into_iter().collect::<Vec<T>>())
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        let bytes: Arc<[u8]> = Arc::from(s.into_bytes());
+        let ptr = ManuallyDrop::new(bytes).ptr;
+        // Safety: the bytes are valid UTF-8, and str has the same layout as [u8].
+        Arc {
+            ptr: unsafe { NonNull::new_unchecked(ptr.as_ptr() as *mut ArcData<str>) },
+        }
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        Arc::from(s.to_owned())
+    }
+}
+
+pub struct Weak<T: ?Sized> {
     ptr: NonNull<ArcData<T>>,
 }
 
-impl<T> Weak<T> {
+impl<T: ?Sized> Weak<T> {
     fn data(&self) -> &ArcData<T> {
         unsafe { self.ptr.as_ref() }
     }
 
+    /// Made by `Weak::new`, there's no ArcData.
+    fn is_dangling(&self) -> bool {
+        (self.ptr.as_ptr() as *mut u8).addr() == usize::MAX
+    }
+
     pub fn upgrade(&self) -> Option<Arc<T>> {
+        if self.is_dangling() {
+            return None;
+        }
         let mut n = self.data().data_ref_count.load(Relaxed);
         loop {
             if n == 0 {
@@ -127,18 +458,63 @@ impl<T> Weak<T> {
             if let Err(e) =
                 self.data()
                     .data_ref_count
-                    .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
+                    .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
             {
                 n = e;
                 continue;
             }
+            // Acquire synchronises with new_cyclic's Release store, the data is initialised.
             return Some(Arc { ptr: self.ptr });
         }
     }
 }
 
-impl<T> Clone for Weak<T> {
+// Only for sized data: the data might already be dropped, so the alignment of unsized data can't be read from it.
+impl<T> Weak<T> {
+    /// A `Weak` that never upgrades, without allocating anything.
+    pub const fn new() -> Self {
+        Weak {
+            // Never a valid address for an ArcData, which is at least two usizes large.
+            ptr: unsafe { NonNull::new_unchecked(ptr::without_provenance_mut(usize::MAX)) },
+        }
+    }
+
+    /// Leaks the `Weak`, giving back a pointer to the (possibly already dropped) data.
+    /// Turn it back into a `Weak` with `from_raw`.
+    pub fn into_raw(weak: Self) -> *const T {
+        let weak = ManuallyDrop::new(weak);
+        // Derived from `ptr`, like in Arc::as_ptr.
+        // Wrapping, because there's nothing to point into for a dangling `Weak`.
+        weak.ptr
+            .as_ptr()
+            .wrapping_byte_add(mem::offset_of!(ArcData<T>, data)) as *const T
+    }
+
+    /// Takes back a `Weak` leaked by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Weak::<T>::into_raw`, and be used to take back the `Weak` only once.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        Weak {
+            ptr: NonNull::new_unchecked(
+                ptr.wrapping_byte_sub(mem::offset_of!(ArcData<T>, data)) as *mut ArcData<T>
+            ),
+        }
+    }
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Weak::new()
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
     fn clone(&self) -> Self {
+        if self.is_dangling() {
+            return Weak { ptr: self.ptr };
+        }
         if self.data().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
             std::process::abort();
         }
@@ -146,8 +522,11 @@ impl<T> Clone for Weak<T> {
     }
 }
 
-impl<T> Drop for Weak<T> {
+impl<T: ?Sized> Drop for Weak<T> {
     fn drop(&mut self) {
+        if self.is_dangling() {
+            return;
+        }
         if self.data().alloc_ref_count.fetch_sub(1, Release) == 1 {
             fence(Acquire);
             unsafe {
@@ -157,10 +536,118 @@ impl<T> Drop for Weak<T> {
     }
 }
 
-unsafe impl<T: Sync + Send> Send for Weak<T> {}
-unsafe impl<T: Sync + Send> Sync for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Send for Weak<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for Weak<T> {}
+
+/// The only pointer to its data, so it can be mutated, before turning it into an `Arc` to share it.
+/// `Weak`s made from it can be handed out, but they don't upgrade until then (like in `Arc::new_cyclic`).
+pub struct UniqueArc<T: ?Sized> {
+    // data_ref_count stays zero until into_arc, so `Weak`s can't upgrade.
+    // alloc_ref_count is one for this, plus the `Weak`s.
+    ptr: NonNull<ArcData<T>>,
+}
+
+impl<T> UniqueArc<T> {
+    pub fn new(data: T) -> Self {
+        let ptr = ArcData::<T>::init_counters(Box::new_uninit(), 0);
+        // Safety: nothing else can see the allocation yet.
+        unsafe {
+            UnsafeCell::raw_get(ptr::addr_of!((*ptr.as_ptr()).data)).write(ManuallyDrop::new(data));
+        }
+        UniqueArc { ptr }
+    }
+
+    /// Allocates for data to be written in place, through `DerefMut`.
+    pub fn new_uninit() -> UniqueArc<MaybeUninit<T>> {
+        UniqueArc {
+            ptr: ArcData::init_counters(Box::new_uninit(), 0),
+        }
+    }
+
+    /// Like `new_uninit`, with the data zeroed.
+    pub fn new_zeroed() -> UniqueArc<MaybeUninit<T>> {
+        UniqueArc {
+            ptr: ArcData::init_counters(Box::new_zeroed(), 0),
+        }
+    }
+}
+
+impl<T> UniqueArc<MaybeUninit<T>> {
+    /// # Safety
+    ///
+    /// The data must have been initialised.
+    pub unsafe fn assume_init(self) -> UniqueArc<T> {
+        // MaybeUninit<T> has the same layout as T.
+        let unique = ManuallyDrop::new(self);
+        UniqueArc {
+            ptr: unique.ptr.cast::<ArcData<T>>(),
+        }
+    }
+}
+
+impl<T: ?Sized> UniqueArc<T> {
+    fn data(&self) -> &ArcData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// A `Weak` that upgrades once this is turned into an `Arc`.
+    pub fn downgrade(unique: &Self) -> Weak<T> {
+        // There's no `Arc`, so there's no get_mut that could have the counter locked.
+        if unique.data().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+        Weak { ptr: unique.ptr }
+    }
+
+    /// Shares the data, in the same allocation.
+    pub fn into_arc(unique: Self) -> Arc<T> {
+        let unique = ManuallyDrop::new(unique);
+        // Release matches the Acquire in upgrade, which makes the changes to the data visible to upgraded pointers.
+        unique.data().data_ref_count.store(1, Release);
+        // This becomes the implicit weak pointer that represents all `Arc`s.
+        Arc { ptr: unique.ptr }
+    }
+}
+
+impl<T: ?Sized> Deref for UniqueArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: nothing else can access the data.
+        unsafe { &*self.data().data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for UniqueArc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: nothing else can access the data, and we have exclusive access to this.
+        unsafe { &mut *self.data().data.get() }
+    }
+}
 
-struct ArcData<T> {
+impl<T: ?Sized> Drop for UniqueArc<T> {
+    fn drop(&mut self) {
+        // Safety: the data was never shared, and isn't accessed anymore.
+        unsafe { ManuallyDrop::drop(&mut *self.data().data.get()) };
+        // Like the last `Arc`.
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for UniqueArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// Like Arc: dropping it drops the data, and the `Weak`s share it once it's an `Arc`.
+unsafe impl<T: ?Sized + Sync + Send> Send for UniqueArc<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for UniqueArc<T> {}
+
+// repr(C) keeps `data` last, after the counters, even when T is unsized,
+// so slices can be allocated as the counters followed by the items.
+#[repr(C)]
+struct ArcData<T: ?Sized> {
     /// Number of `Arc`s.
     data_ref_count: AtomicUsize,
     /// Number of `Weak`s, plus one if there are any `Arc`s.
@@ -177,6 +664,20 @@ impl<T> ArcData<T> {
             data: UnsafeCell::new(ManuallyDrop::new(data)),
         }
     }
+
+    /// Sets up the counters of a fresh allocation, leaving the data as it is (uninitialised or zeroed).
+    fn init_counters(
+        mut alloc: Box<MaybeUninit<ArcData<T>>>,
+        data_ref_count: usize,
+    ) -> NonNull<ArcData<T>> {
+        let ptr = alloc.as_mut_ptr();
+        // Safety: writing the counters through raw pointers, the data isn't touched.
+        unsafe {
+            ptr::addr_of_mut!((*ptr).data_ref_count).write(AtomicUsize::new(data_ref_count));
+            ptr::addr_of_mut!((*ptr).alloc_ref_count).write(AtomicUsize::new(1));
+        }
+        NonNull::new(Box::into_raw(alloc).cast::<ArcData<T>>()).unwrap()
+    }
 }
 
 #[cfg(test)]
@@ -220,4 +721,390 @@ mod test {
         assert_eq!(NUM_DROPS.load(Relaxed), 1);
         assert!(z.upgrade().is_none());
     }
+
+    #[test]
+    fn test_counts_and_ptr_eq() {
+        let five = Arc::new(5);
+        let other_five = Arc::new(5);
+        let same_five = five.clone();
+        let weak = Arc::downgrade(&five);
+        assert_eq!(Arc::strong_count(&five), 2);
+        assert_eq!(Arc::weak_count(&five), 1);
+        assert!(Arc::ptr_eq(&five, &same_five));
+        assert!(!Arc::ptr_eq(&five, &other_five));
+        assert_eq!(Arc::as_ptr(&five), Arc::as_ptr(&same_five));
+        assert_eq!(unsafe { *Arc::as_ptr(&five) }, 5);
+        drop(weak);
+        drop(same_five);
+        assert_eq!(Arc::strong_count(&five), 1);
+        assert_eq!(Arc::weak_count(&five), 0);
+    }
+
+    #[test]
+    fn test_try_unwrap() {
+        let x = Arc::new(3);
+        assert_eq!(Arc::try_unwrap(x), Ok(3));
+
+        let x = Arc::new(4);
+        let _y = x.clone();
+        assert_eq!(*Arc::try_unwrap(x).unwrap_err(), 4);
+
+        // weak pointers don't prevent unwrapping, but can't upgrade afterwards
+        let x = Arc::new(String::from("hello"));
+        let weak = Arc::downgrade(&x);
+        assert_eq!(Arc::try_unwrap(x).unwrap(), "hello");
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_into_inner() {
+        let x = Arc::new(3);
+        let y = x.clone();
+        // exactly one of the two threads gets the data
+        let x_thread = std::thread::spawn(|| Arc::into_inner(x));
+        let y_thread = std::thread::spawn(|| Arc::into_inner(y));
+        let x_inner = x_thread.join().unwrap();
+        let y_inner = y_thread.join().unwrap();
+        assert!(matches!(
+            (x_inner, y_inner),
+            (None, Some(3)) | (Some(3), None)
+        ));
+    }
+
+    #[test]
+    fn test_unwrap_or_clone() {
+        let inner = String::from("test");
+        let ptr = inner.as_ptr();
+        let arc = Arc::new(inner);
+        let inner = Arc::unwrap_or_clone(arc);
+        // the inner value wasn't cloned
+        assert_eq!(inner.as_ptr(), ptr);
+
+        let arc = Arc::new(inner);
+        let arc2 = arc.clone();
+        let inner = Arc::unwrap_or_clone(arc);
+        // cloned, because there was another Arc
+        assert_ne!(inner.as_ptr(), ptr);
+        // the last one isn't cloned
+        let inner = Arc::unwrap_or_clone(arc2);
+        assert_eq!(inner.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_make_mut() {
+        let mut data = Arc::new(5);
+        *Arc::make_mut(&mut data) += 1; // won't clone anything
+        let mut other_data = data.clone(); // won't clone inner data
+        *Arc::make_mut(&mut data) += 1; // clones inner data
+        *Arc::make_mut(&mut data) += 1; // won't clone anything
+        *Arc::make_mut(&mut other_data) *= 2; // won't clone anything
+        assert_eq!(*data, 8);
+        assert_eq!(*other_data, 12);
+        assert!(!Arc::ptr_eq(&data, &other_data));
+
+        // weak pointers are disassociated instead of cloning
+        let mut data = Arc::new(75);
+        let weak = Arc::downgrade(&data);
+        assert_eq!(*data, 75);
+        assert_eq!(*weak.upgrade().unwrap(), 75);
+        *Arc::make_mut(&mut data) += 1;
+        assert_eq!(*data, 76);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(Arc::weak_count(&data), 0);
+    }
+
+    #[test]
+    fn test_make_mut_drops() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        // moving the data away from weak pointers doesn't drop it
+        let mut x = Arc::new(DetectDrop);
+        let weak = Arc::downgrade(&x);
+        Arc::make_mut(&mut x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(weak);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        // cloning drops neither copy
+        let mut x = Arc::new(DetectDrop);
+        let y = x.clone();
+        Arc::make_mut(&mut x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        drop((x, y));
+        assert_eq!(NUM_DROPS.load(Relaxed), 3);
+    }
+
+    #[test]
+    fn test_trait_impls() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+
+        let a: Arc<i32> = 5.into();
+        let b = Arc::new(7);
+        assert_eq!(format!("{a:?} {b}"), "5 7");
+        assert_eq!(a, Arc::new(5));
+        assert_ne!(a, b);
+        assert!(a < b);
+        assert_eq!(a.cmp(&b), Ordering::Less);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+
+        let hash = |x: &dyn Fn(&mut DefaultHasher)| {
+            let mut hasher = DefaultHasher::new();
+            x(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&|h| a.hash(h)), hash(&|h| 5.hash(h)));
+
+        // Borrow lets collections of Arcs be looked up by the data
+        let set: HashSet<Arc<i32>> = [a.clone(), b.clone()].into_iter().collect();
+        assert!(set.contains(&5));
+        assert_eq!(AsRef::<i32>::as_ref(&b), &7);
+
+        let default: Arc<Vec<i32>> = Arc::default();
+        assert!(default.is_empty());
+    }
+
+    #[test]
+    fn test_slice_drops_every_item() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let x: Arc<[DetectDrop]> = (0..5).map(DetectDrop).collect();
+        let weak = Arc::downgrade(&x);
+        assert_eq!(
+            weak.upgrade().unwrap().iter().map(|d| d.0).sum::<usize>(),
+            10
+        );
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+        drop(x);
+        // dropped while the weak pointer keeps the allocation around
+        assert_eq!(NUM_DROPS.load(Relaxed), 5);
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        assert_eq!(NUM_DROPS.load(Relaxed), 5);
+    }
+
+    #[test]
+    fn test_str() {
+        let mut x: Arc<str> = Arc::from("hello");
+        let y = Arc::from(String::from("hello"));
+        assert_eq!(x, y);
+        assert!(!Arc::ptr_eq(&x, &y));
+        assert_eq!(x.to_string(), "hello");
+        assert_eq!(
+            Arc::get_mut(&mut x).map(|s| s.to_uppercase()),
+            Some("HELLO".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_unsize() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        impl fmt::Display for DetectDrop {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("DetectDrop")
+            }
+        }
+
+        let x = Arc::new(DetectDrop);
+        let y = crate::unsize!(x.clone() => Arc<dyn fmt::Display>);
+        let weak = Arc::downgrade(&y);
+        assert_eq!(y.to_string(), "DetectDrop");
+        assert_eq!(Arc::strong_count(&y), 2);
+        drop(x);
+        assert_eq!(weak.upgrade().unwrap().to_string(), "DetectDrop");
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(&'static str);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        impl fmt::Display for DetectDrop {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+
+        let x = Arc::new(DetectDrop("hello"));
+        let weak = Weak::into_raw(Arc::downgrade(&x));
+        let ptr = Arc::into_raw(x);
+        // both point at the data itself
+        assert_eq!(ptr, weak);
+        assert_eq!(unsafe { (*ptr).0 }, "hello");
+
+        unsafe { Arc::increment_strong_count(ptr) };
+        let x = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(Arc::strong_count(&x), 2);
+        assert_eq!(Arc::weak_count(&x), 1);
+        drop(x);
+        unsafe { Arc::decrement_strong_count(ptr) };
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        // the weak pointer still works after the data is gone
+        let weak = unsafe { Weak::from_raw(weak) };
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+
+        // unsized
+        let x = crate::unsize!(Arc::new(DetectDrop("dyn")) => Arc<dyn fmt::Display>);
+        let ptr = Arc::into_raw(x);
+        assert_eq!(unsafe { (*ptr).to_string() }, "dyn");
+        let x = unsafe { Arc::from_raw(ptr) };
+        let weak = Arc::downgrade(&x);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), 2);
+        assert!(weak.upgrade().is_none());
+
+        let x: Arc<[u128]> = Arc::from(vec![1, 2, 3]);
+        let x = unsafe { Arc::from_raw(Arc::into_raw(x)) };
+        assert_eq!(*x, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_new_cyclic() {
+        struct Node {
+            me: Weak<Node>,
+            children: Vec<Arc<Child>>,
+        }
+
+        struct Child {
+            parent: Weak<Node>,
+        }
+
+        let node = Arc::new_cyclic(|me| {
+            // not upgradable while the node is being made
+            assert!(me.upgrade().is_none());
+            let children = (0..2)
+                .map(|_| Arc::new(Child { parent: me.clone() }))
+                .collect();
+            Node {
+                me: me.clone(),
+                children,
+            }
+        });
+        assert!(Arc::ptr_eq(&node.me.upgrade().unwrap(), &node));
+        for child in &node.children {
+            assert!(Arc::ptr_eq(&child.parent.upgrade().unwrap(), &node));
+        }
+        assert_eq!(Arc::strong_count(&node), 1);
+        assert_eq!(Arc::weak_count(&node), 3);
+
+        let child = node.children[0].clone();
+        drop(node);
+        assert!(child.parent.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_new_cyclic_panics() {
+        let result = std::panic::catch_unwind(|| {
+            Arc::<i32>::new_cyclic(|me| {
+                let _escaped = me.clone();
+                panic!("boom");
+            })
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dangling_weak() {
+        let weak: Weak<String> = Weak::new();
+        assert!(weak.upgrade().is_none());
+        let clone = weak.clone();
+        assert!(clone.upgrade().is_none());
+        drop((weak, clone));
+
+        let weak = unsafe { Weak::from_raw(Weak::into_raw(Weak::<u64>::default())) };
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_unique_arc() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let mut unique = UniqueArc::new(vec![1, 2]);
+        let weak = UniqueArc::downgrade(&unique);
+        unique.push(3);
+        // not shared yet
+        assert!(weak.upgrade().is_none());
+        let data_ptr = &*unique as *const Vec<i32>;
+        let arc = UniqueArc::into_arc(unique);
+        assert_eq!(Arc::as_ptr(&arc), data_ptr);
+        assert_eq!(Arc::strong_count(&arc), 1);
+        assert_eq!(Arc::weak_count(&arc), 1);
+        assert_eq!(*weak.upgrade().unwrap(), [1, 2, 3]);
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+
+        // dropped without being shared, the weak pointers keep only the allocation
+        let unique = UniqueArc::new(DetectDrop);
+        let weak = UniqueArc::downgrade(&unique);
+        drop(unique);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_uninit() {
+        let mut unique = UniqueArc::<[u64; 4]>::new_uninit();
+        unique.write([1, 2, 3, 4]);
+        let arc = UniqueArc::into_arc(unsafe { unique.assume_init() });
+        assert_eq!(*arc, [1, 2, 3, 4]);
+
+        let mut arc = Arc::<String>::new_uninit();
+        Arc::get_mut(&mut arc).unwrap().write(String::from("hello"));
+        let arc = unsafe { arc.assume_init() };
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(*weak.upgrade().unwrap(), "hello");
+
+        let arc = unsafe { Arc::<[u64; 4]>::new_zeroed().assume_init() };
+        assert_eq!(*arc, [0; 4]);
+        let unique = unsafe { UniqueArc::<u128>::new_zeroed().assume_init() };
+        assert_eq!(*unique, 0);
+    }
 }
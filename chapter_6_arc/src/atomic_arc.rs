@@ -0,0 +1,200 @@
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::Mutex;
+
+use crate::arc::Arc;
+
+// An `Arc` that can be replaced while other threads are loading it.
+//
+// The problem with just keeping `Arc::into_raw` in an AtomicPtr:
+// a load reads the pointer, and only then increments the reference count.
+// In between, a swap might replace the pointer and drop the last `Arc`, freeing what the load is about to use.
+//
+// So loads announce themselves in a reader counter before reading the pointer,
+// and leave again after incrementing the reference count.
+// A swap waits for the loads that might have read the old pointer to leave before giving back its `Arc`.
+// Waiting for a single counter to reach zero could take forever with loads constantly coming in,
+// so there are two of them, and loads use the one that `epoch` points at.
+// A swap moves `epoch` to the other counter, so the one it's waiting for only has loads leaving it.
+// (The same idea as the left-right concurrency technique.)
+//
+// Loads never block. Swaps are serialised by a mutex, and wait for the loads in flight to finish.
+// Everything is SeqCst: the reasoning below relies on a single total order of
+// a load's counter increment and pointer read, and a swap's pointer swap and counter reads.
+pub struct AtomicArc<T> {
+    ptr: AtomicPtr<T>,         // from Arc::into_raw
+    readers: [AtomicUsize; 2], // loads in progress
+    epoch: AtomicUsize,        // which counter new loads use, only changed by swaps
+    writer: Mutex<()>,         // one swap at a time
+    _arc: PhantomData<Arc<T>>, // Send and Sync only if Arc<T> is
+}
+
+impl<T> AtomicArc<T> {
+    pub fn new(arc: Arc<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Arc::into_raw(arc) as *mut T),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            epoch: AtomicUsize::new(0),
+            writer: Mutex::new(()),
+            _arc: PhantomData,
+        }
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        let readers = &self.readers[self.epoch.load(SeqCst)];
+        readers.fetch_add(1, SeqCst);
+        let ptr = self.ptr.load(SeqCst);
+        // Safety: the swap that replaces this pointer waits for us to leave before giving back its `Arc`,
+        // so there's still at least that one.
+        let arc = unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        };
+        readers.fetch_sub(1, SeqCst);
+        arc
+    }
+
+    pub fn store(&self, arc: Arc<T>) {
+        drop(self.swap(arc));
+    }
+
+    /// Replaces the `Arc`, giving back the previous one.
+    pub fn swap(&self, arc: Arc<T>) -> Arc<T> {
+        let _writer = self.writer.lock().unwrap();
+        let old = self.ptr.swap(Arc::into_raw(arc) as *mut T, SeqCst);
+        self.wait_for_readers();
+        // Safety: it came from Arc::into_raw, and no load is about to use it anymore.
+        unsafe { Arc::from_raw(old) }
+    }
+
+    /// Replaces the `Arc` with `new` if it's still `current`, giving back the previous one.
+    /// Otherwise gives `new` back.
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let _writer = self.writer.lock().unwrap();
+        let new = Arc::into_raw(new) as *mut T;
+        match self
+            .ptr
+            .compare_exchange(Arc::as_ptr(current) as *mut T, new, SeqCst, SeqCst)
+        {
+            Ok(old) => {
+                self.wait_for_readers();
+                // Safety: like in swap.
+                Ok(unsafe { Arc::from_raw(old) })
+            }
+            // Safety: we just made it with into_raw, and nobody else saw it.
+            Err(_) => Err(unsafe { Arc::from_raw(new) }),
+        }
+    }
+
+    /// Waits for the loads that might have read the pointer from before the (just made) swap
+    /// to have incremented its reference count.
+    /// Those loads announced themselves in either counter before reading the pointer,
+    /// so both counters have to be seen at zero, but not at the same time:
+    /// first the one new loads aren't using, then switch and do the other one.
+    fn wait_for_readers(&self) {
+        // Only swaps change the epoch, and we're the only swap.
+        let epoch = self.epoch.load(SeqCst);
+        wait_until_zero(&self.readers[1 - epoch]);
+        self.epoch.store(1 - epoch, SeqCst);
+        wait_until_zero(&self.readers[epoch]);
+    }
+}
+
+fn wait_until_zero(readers: &AtomicUsize) {
+    // Loads only take a moment, there's no point in parking.
+    while readers.load(SeqCst) != 0 {
+        std::hint::spin_loop();
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // Safety: it came from Arc::into_raw, and nothing else can use it anymore.
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let x = AtomicArc::new(Arc::new(1));
+        assert_eq!(*x.load(), 1);
+        let old = x.swap(Arc::new(2));
+        assert_eq!(*old, 1);
+        x.store(Arc::new(3));
+        let three = x.load();
+
+        // compare_and_swap looks at the pointer, not at the value
+        let Err(new) = x.compare_and_swap(&Arc::new(3), Arc::new(4)) else {
+            panic!("swapped out a different Arc");
+        };
+        assert_eq!(*new, 4);
+        let Ok(old) = x.compare_and_swap(&three, new) else {
+            panic!("didn't swap out the same Arc");
+        };
+        assert_eq!(*old, 3);
+        assert_eq!(*x.load(), 4);
+    }
+
+    #[test]
+    fn test_stress() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        const WRITERS: usize = 2;
+        const SWAPS: usize = 2000;
+
+        let x = AtomicArc::new(Arc::new(DetectDrop(0)));
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Relaxed) {
+                        let arc = x.load();
+                        // a value that was freed would likely be garbage by now
+                        assert!(arc.0 <= WRITERS * SWAPS);
+                    }
+                });
+            }
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|w| {
+                    let x = &x;
+                    s.spawn(move || {
+                        for i in 0..SWAPS {
+                            let value = 1 + w * SWAPS + i;
+                            if i % 2 == 0 {
+                                drop(x.swap(Arc::new(DetectDrop(value))));
+                            } else {
+                                let current = x.load();
+                                // might lose against the other writer
+                                drop(x.compare_and_swap(&current, Arc::new(DetectDrop(value))));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for w in writers {
+                w.join().unwrap();
+            }
+            done.store(true, Relaxed);
+        });
+        // everything but the current value is dropped, exactly once
+        assert_eq!(NUM_DROPS.load(Relaxed), WRITERS * SWAPS);
+        drop(x);
+        assert_eq!(NUM_DROPS.load(Relaxed), WRITERS * SWAPS + 1);
+    }
+}
@@ -1,4 +1,7 @@
+pub mod alloc;
 pub mod arc;
 pub mod arc_weak;
 pub mod arc_weak_opt;
+pub mod atomic_arc;
+pub mod thin_arc;
 mod utils;
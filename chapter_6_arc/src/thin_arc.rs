@@ -0,0 +1,183 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+
+use crate::arc::Arc;
+
+/// A header followed by a slice, in one allocation when it's in an `Arc` (see `Arc::from_header_and_iter`).
+/// Also keeps the length, so `ThinArc` doesn't need a fat pointer.
+#[repr(C)]
+pub struct HeaderSlice<H, T: ?Sized> {
+    pub header: H,
+    len: usize, // always slice.len(), only made by Arc::from_header_and_iter
+    pub slice: T,
+}
+
+impl<H, T> HeaderSlice<H, [T]> {
+    /// The sized version, with the same fields at the same offsets.
+    /// (Its size is padded up to its alignment, so it can be larger than the offset of the slice.)
+    pub(crate) fn header(header: H, len: usize) -> HeaderSlice<H, [T; 0]> {
+        HeaderSlice {
+            header,
+            len,
+            slice: [],
+        }
+    }
+}
+
+impl<H: fmt::Debug, T: fmt::Debug> fmt::Debug for HeaderSlice<H, [T]> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderSlice")
+            .field("header", &self.header)
+            .field("slice", &&self.slice)
+            .finish()
+    }
+}
+
+// An `Arc<HeaderSlice<H, [T]>>` is two words: the pointer and the length.
+// This is a single word: it points at the sized start of the HeaderSlice,
+// and reads the length from there to make the fat pointer again when needed.
+// Otherwise it's the same `Arc`, with the same reference count in front of the data.
+pub struct ThinArc<H, T> {
+    ptr: NonNull<HeaderSlice<H, [T; 0]>>, // from Arc::into_raw
+    _arc: PhantomData<Arc<HeaderSlice<H, [T]>>>, // Send and Sync only if that Arc is
+}
+
+impl<H, T> ThinArc<H, T> {
+    pub fn from_header_and_iter(header: H, items: impl IntoIterator<Item = T>) -> Self {
+        ThinArc::from(Arc::from_header_and_iter(header, items))
+    }
+
+    /// The pointer with the length as metadata, like in `Arc<HeaderSlice<H, [T]>>`.
+    fn fat_ptr(&self) -> *const HeaderSlice<H, [T]> {
+        let ptr = self.ptr.as_ptr();
+        // Safety: the data is alive as long as there's a ThinArc.
+        let len = unsafe { ptr::addr_of!((*ptr).len).read() };
+        // A pointer cast keeps the metadata: the slice length becomes the length of the last field.
+        ptr::slice_from_raw_parts(ptr.cast::<T>(), len) as *const HeaderSlice<H, [T]>
+    }
+
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        a.ptr == b.ptr
+    }
+}
+
+impl<H, T> From<Arc<HeaderSlice<H, [T]>>> for ThinArc<H, T> {
+    fn from(arc: Arc<HeaderSlice<H, [T]>>) -> Self {
+        ThinArc {
+            ptr: NonNull::new(Arc::into_raw(arc) as *mut HeaderSlice<H, [T; 0]>).unwrap(),
+            _arc: PhantomData,
+        }
+    }
+}
+
+impl<H, T> From<ThinArc<H, T>> for Arc<HeaderSlice<H, [T]>> {
+    fn from(thin: ThinArc<H, T>) -> Self {
+        let thin = ManuallyDrop::new(thin);
+        // Safety: it came from Arc::into_raw, and the ThinArc is gone.
+        unsafe { Arc::from_raw(thin.fat_ptr()) }
+    }
+}
+
+impl<H, T> Deref for ThinArc<H, T> {
+    type Target = HeaderSlice<H, [T]>;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: the data is alive as long as there's a ThinArc.
+        unsafe { &*self.fat_ptr() }
+    }
+}
+
+impl<H, T> Clone for ThinArc<H, T> {
+    fn clone(&self) -> Self {
+        // Safety: it came from Arc::into_raw, and is still ours.
+        unsafe { Arc::increment_strong_count(self.fat_ptr()) };
+        ThinArc {
+            ptr: self.ptr,
+            _arc: PhantomData,
+        }
+    }
+}
+
+impl<H, T> Drop for ThinArc<H, T> {
+    fn drop(&mut self) {
+        // Safety: it came from Arc::into_raw, and isn't used anymore.
+        unsafe { Arc::decrement_strong_count(self.fat_ptr()) };
+    }
+}
+
+unsafe impl<H: Send + Sync, T: Send + Sync> Send for ThinArc<H, T> {}
+unsafe impl<H: Send + Sync, T: Send + Sync> Sync for ThinArc<H, T> {}
+
+impl<H: fmt::Debug, T: fmt::Debug> fmt::Debug for ThinArc<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::mem::size_of;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    #[test]
+    fn test() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        assert_eq!(size_of::<ThinArc<String, u8>>(), size_of::<usize>());
+        assert_eq!(size_of::<Option<ThinArc<String, u8>>>(), size_of::<usize>());
+
+        let x = ThinArc::from_header_and_iter(DetectDrop(100), (0..5).map(DetectDrop));
+        let y = x.clone();
+        assert!(ThinArc::ptr_eq(&x, &y));
+        assert_eq!(x.header.0, 100);
+        assert_eq!(y.slice.len(), 5);
+        assert_eq!(y.slice.iter().map(|d| d.0).sum::<usize>(), 10);
+
+        let t = std::thread::spawn(move || x.slice[4].0);
+        assert_eq!(t.join().unwrap(), 4);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+
+        // and back, in the same allocation
+        let arc: Arc<HeaderSlice<_, [_]>> = Arc::from(y);
+        let y = ThinArc::from(arc);
+        assert_eq!(y.slice[2].0, 2);
+        drop(y);
+        assert_eq!(NUM_DROPS.load(Relaxed), 6);
+    }
+
+    #[test]
+    fn test_layouts() {
+        // the sized header ends with padding that's taken up by the items:
+        // the slice is right after the length, at 24, but the header is padded to 32
+        let x = ThinArc::from_header_and_iter(1u128, [2u8, 3, 4]);
+        assert_eq!((x.header, &x.slice), (1, &[2, 3, 4][..]));
+
+        // the items are more aligned than the header
+        #[repr(align(32))]
+        #[derive(Debug, PartialEq)]
+        struct Aligned(u8);
+
+        let x = ThinArc::from_header_and_iter((), [Aligned(1), Aligned(2)]);
+        assert_eq!(x.slice.as_ptr() as usize % 32, 0);
+        assert_eq!(x.slice, [Aligned(1), Aligned(2)]);
+
+        let empty = ThinArc::from_header_and_iter(String::from("empty"), Vec::<u64>::new());
+        assert_eq!(
+            format!("{empty:?}"),
+            r#"HeaderSlice { header: "empty", slice: [] }"#
+        );
+    }
+}
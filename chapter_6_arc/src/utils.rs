@@ -1,5 +1,79 @@
-use std::ptr::NonNull;
+use std::alloc::{alloc, handle_alloc_error, Layout};
+use std::mem;
+use std::ptr::{self, NonNull};
 
 pub(crate) fn non_null_from<T>(x: T) -> NonNull<T> {
     NonNull::from(Box::leak(Box::new(x)))
 }
+
+/// Allocates a `#[repr(C)]` struct that starts with the fields of `header` and ends in `[T]`,
+/// moving `items` into the slice.
+/// `H` must be the struct itself with a zero sized last field,
+/// so that the other fields are at the same offsets in both.
+///
+/// The returned pointer points to the start of the struct, and has the slice length as metadata,
+/// so it can be cast to a pointer to the struct.
+pub(crate) fn alloc_with_slice<H, T>(header: H, items: Vec<T>) -> NonNull<[T]> {
+    alloc_with_slice_at(header, last_field_offset::<H>(mem::align_of::<T>()), items)
+}
+
+/// Like `alloc_with_slice`, with the slice at `offset` instead of right after `H`.
+/// For when the last field is nested in another struct, whose padding makes `H` larger than that offset.
+/// `H` is written first, so its padding can overlap the slice.
+pub(crate) fn alloc_with_slice_at<H, T>(
+    header: H,
+    offset: usize,
+    mut items: Vec<T>,
+) -> NonNull<[T]> {
+    let size = offset + mem::size_of::<T>() * items.len();
+    let align = mem::align_of::<H>().max(mem::align_of::<T>());
+    // A repr(C) struct is padded up to its alignment.
+    let layout = Layout::from_size_align(size, align).unwrap().pad_to_align();
+    assert!(layout.size() >= mem::size_of::<H>());
+    // Safety: the layout isn't zero sized, the header is in it.
+    let mem = unsafe { alloc(layout) };
+    if mem.is_null() {
+        handle_alloc_error(layout);
+    }
+    let len = items.len();
+    // Safety: both fit in the allocation, and the items are moved: the vec forgets about them.
+    unsafe {
+        mem.cast::<H>().write(header);
+        ptr::copy_nonoverlapping(items.as_ptr(), mem.add(offset).cast::<T>(), len);
+        items.set_len(0);
+    }
+    NonNull::new(ptr::slice_from_raw_parts_mut(mem.cast::<T>(), len)).unwrap()
+}
+
+/// The offset of the last field of a `#[repr(C)]` struct when that field is aligned to `align`.
+/// `H` is the struct with a zero sized last field, like in `alloc_with_slice`.
+/// The other fields must end at the size of `H`, without padding after them,
+/// otherwise the last field starts inside that padding.
+/// (`offset_of!` doesn't work for unsized fields, whose offset depends on their alignment.)
+pub(crate) fn last_field_offset<H>(align: usize) -> usize {
+    let (_, offset) = Layout::new::<H>()
+        .extend(Layout::from_size_align(0, align).unwrap())
+        .unwrap();
+    offset
+}
+
+/// Converts an `Arc<T>` into an `Arc<U>` when `T` unsizes to `U`, like `Arc<[i32; 3]>` to `Arc<[i32]>`
+/// or `Arc<String>` to `Arc<dyn Display>`, which std's Arc does implicitly
+/// (an allocator after the target type, like `Arc<dyn Display, &Arena>`, is allowed but not needed):
+///
+/// ```
+/// use chapter_6_arc::{arc::Arc, unsize};
+/// use std::fmt::Display;
+///
+/// let arc = Arc::new(String::from("hello"));
+/// let arc = unsize!(arc => Arc<dyn Display>);
+/// assert_eq!(arc.to_string(), "hello");
+/// ```
+#[macro_export]
+macro_rules! unsize {
+    ($arc:expr => $($arc_type:ident)::+ <$target:ty $(, $alloc:ty)?>) => {{
+        let arc = $arc;
+        // Safety: the only thing the closure can do to the pointer is coerce it, which keeps the address.
+        unsafe { $($arc_type)::+::unsize(arc, |data| -> *const $target { data }) }
+    }};
+}
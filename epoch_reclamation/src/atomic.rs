@@ -0,0 +1,208 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::collector::Guard;
+
+// The pointer types for data protected by a collector:
+//
+// `Atomic<T>` is the shared pointer, like an `AtomicPtr<T>` to a box. It doesn't drop the box, the data structure does.
+// `Owned<T>` is a box nobody else has seen yet.
+// `Shared<'g, T>` is a pointer loaded from an `Atomic<T>` while pinned.
+// It borrows the guard, so it can't be used anymore once the thread might be unpinned.
+
+pub struct Atomic<T> {
+    ptr: AtomicPtr<T>,
+    _box: PhantomData<Box<T>>,
+}
+
+pub struct Owned<T> {
+    data: Box<T>,
+}
+
+pub struct Shared<'g, T> {
+    ptr: *mut T,
+    _guard: PhantomData<&'g T>,
+}
+
+unsafe impl<T: Send + Sync> Send for Atomic<T> {}
+unsafe impl<T: Send + Sync> Sync for Atomic<T> {}
+
+impl<T> Default for Atomic<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> Atomic<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(data))),
+            _box: PhantomData,
+        }
+    }
+
+    pub const fn null() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            _box: PhantomData,
+        }
+    }
+
+    pub fn load<'g>(&self, ordering: Ordering, _guard: &'g Guard) -> Shared<'g, T> {
+        Shared::from_raw(self.ptr.load(ordering))
+    }
+
+    pub fn store(&self, new: Shared<'_, T>, ordering: Ordering) {
+        self.ptr.store(new.ptr, ordering);
+    }
+
+    pub fn swap<'g>(
+        &self,
+        new: Shared<'_, T>,
+        ordering: Ordering,
+        _guard: &'g Guard,
+    ) -> Shared<'g, T> {
+        Shared::from_raw(self.ptr.swap(new.ptr, ordering))
+    }
+
+    /// Replaces the pointer with `new` if it's still `current`, giving back the previous one.
+    /// Otherwise gives back the current one.
+    pub fn compare_exchange<'g>(
+        &self,
+        current: Shared<'_, T>,
+        new: Shared<'_, T>,
+        success: Ordering,
+        failure: Ordering,
+        _guard: &'g Guard,
+    ) -> Result<Shared<'g, T>, Shared<'g, T>> {
+        self.ptr
+            .compare_exchange(current.ptr, new.ptr, success, failure)
+            .map(Shared::from_raw)
+            .map_err(Shared::from_raw)
+    }
+
+    /// Reads the pointer without pinning, for when nothing else can access the `Atomic` anymore.
+    pub fn get_mut(&mut self) -> *mut T {
+        *self.ptr.get_mut()
+    }
+
+    /// Takes the box back, for when nobody else can see the pointer anymore.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must not be null, and nothing else may destroy what it points to.
+    pub unsafe fn into_owned(self) -> Owned<T> {
+        Owned {
+            data: Box::from_raw(self.ptr.into_inner()),
+        }
+    }
+}
+
+impl<T> From<Owned<T>> for Atomic<T> {
+    fn from(owned: Owned<T>) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(owned.data)),
+            _box: PhantomData,
+        }
+    }
+}
+
+impl<T> Owned<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data: Box::new(data),
+        }
+    }
+
+    /// Gives up ownership, for storing it in an `Atomic<T>`.
+    /// If it doesn't end up there, take it back with `Shared::into_owned` to not leak it.
+    pub fn into_shared<'g>(self, _guard: &'g Guard) -> Shared<'g, T> {
+        Shared::from_raw(Box::into_raw(self.data))
+    }
+
+    pub fn into_box(self) -> Box<T> {
+        self.data
+    }
+}
+
+impl<T> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for Owned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T> Clone for Shared<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Shared<'_, T> {}
+
+impl<T> PartialEq for Shared<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.ptr, other.ptr)
+    }
+}
+
+impl<T> Eq for Shared<'_, T> {}
+
+impl<'g, T> Shared<'g, T> {
+    pub fn null() -> Self {
+        Self::from_raw(ptr::null_mut())
+    }
+
+    /// Without a guard, the caller makes sure nobody frees `ptr` while the `Shared` is around.
+    pub(crate) fn from_raw(ptr: *mut T) -> Self {
+        Self {
+            ptr,
+            _guard: PhantomData,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    pub fn as_raw(&self) -> *const T {
+        self.ptr
+    }
+
+    /// # Safety
+    ///
+    /// The pointer must not be null, and what it points to must not have been destroyed
+    /// before the guard was pinned.
+    pub unsafe fn deref(&self) -> &'g T {
+        &*self.ptr
+    }
+
+    /// Like `deref`, but `None` for a null pointer.
+    ///
+    /// # Safety
+    ///
+    /// Like `deref`.
+    pub unsafe fn as_ref(&self) -> Option<&'g T> {
+        self.ptr.as_ref()
+    }
+
+    /// Takes the box back.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must not be null, and nobody else may be able to use or destroy what it points to.
+    pub unsafe fn into_owned(self) -> Owned<T> {
+        Owned {
+            data: Box::from_raw(self.ptr),
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
+
+use crate::atomic::Shared;
+
+// Epoch-based reclamation (Keir Fraser, "Practical lock-freedom").
+//
+// There's a global epoch, and every thread working with the shared data first pins itself:
+// it records the global epoch it saw in its own record, and keeps pointers it loaded only while pinned.
+// An object removed from the shared data is deferred: tagged with the global epoch and put on a garbage list.
+//
+// The global epoch only advances when every pinned thread has seen the current one.
+// So once it's two epochs past an object's tag, every thread that was pinned when the object was
+// removed has unpinned since, and nobody can still have a pointer to it.
+//
+// Records live in a lock-free linked list that only grows: a released record is reused, not freed.
+// The garbage is in a lock-free linked list too: a collection takes the whole list,
+// frees what's old enough, and puts back the rest.
+
+/// Try to collect once every this many pins (of the same record).
+const PINS_BETWEEN_COLLECT: usize = 128;
+
+pub struct Collector {
+    epoch: AtomicUsize,
+    records: AtomicPtr<Record>,
+    garbage: AtomicPtr<Garbage>,
+    garbage_count: AtomicUsize,
+}
+
+/// What the collector knows about a thread.
+struct Record {
+    /// The epoch the thread is pinned in, shifted left by one, with the lowest bit set.
+    /// Zero if the thread isn't pinned.
+    epoch: AtomicUsize,
+    in_use: AtomicBool,
+    next: *const Record, // never changes after the record is in the list
+    // Only used by the thread that owns the record, see LocalHandle.
+    guard_count: Cell<usize>,
+    pin_count: Cell<usize>,
+    has_handle: Cell<bool>,
+}
+
+// The cells are only used by the thread that owns the record,
+// the other fields are atomic or never change.
+unsafe impl Sync for Record {}
+
+/// A deferred destruction.
+struct Garbage {
+    epoch: usize,
+    ptr: *mut u8,
+    destroy: unsafe fn(*mut u8), // drops the box it came from, with the right type
+    next: *mut Garbage,
+}
+
+unsafe fn destroy<T>(ptr: *mut u8) {
+    drop(Box::from_raw(ptr.cast::<T>()));
+}
+
+// The raw pointers are only used as described above.
+unsafe impl Send for Collector {}
+unsafe impl Sync for Collector {}
+
+static COLLECTOR: Collector = Collector::new();
+
+thread_local! {
+    static HANDLE: LocalHandle<'static> = COLLECTOR.register();
+}
+
+/// Pins the current thread with the global collector.
+pub fn pin() -> Guard<'static> {
+    // The handle is gone if this is called from another thread local's destructor,
+    // a temporary one is fine: the record is released when the guard is dropped.
+    HANDLE
+        .try_with(|handle| handle.pin())
+        .unwrap_or_else(|_| COLLECTOR.register().pin())
+}
+
+pub fn default_collector() -> &'static Collector {
+    &COLLECTOR
+}
+
+/// A thread's registration with a collector.
+/// Not `Send`: the record it owns is only used by this thread.
+pub struct LocalHandle<'c> {
+    collector: &'c Collector,
+    record: &'c Record,
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Keeps the thread pinned, pointers loaded with it stay valid until it's dropped.
+/// Guards can be nested, the thread is unpinned when the last one is dropped.
+pub struct Guard<'c> {
+    collector: &'c Collector,
+    record: &'c Record,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Collector {
+    pub const fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            records: AtomicPtr::new(ptr::null_mut()),
+            garbage: AtomicPtr::new(ptr::null_mut()),
+            garbage_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reuses a released record, or adds one.
+    pub fn register(&self) -> LocalHandle<'_> {
+        LocalHandle {
+            collector: self,
+            record: self.acquire_record(),
+            _not_send: PhantomData,
+        }
+    }
+
+    fn acquire_record(&self) -> &Record {
+        let mut record = self.records.load(Acquire);
+        while !record.is_null() {
+            // Safety: records are only freed along with the collector.
+            let r = unsafe { &*record };
+            if !r.in_use.load(Relaxed)
+                && r.in_use
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+            {
+                r.has_handle.set(true);
+                return r;
+            }
+            record = r.next as *mut Record;
+        }
+        let new = Box::into_raw(Box::new(Record {
+            epoch: AtomicUsize::new(0),
+            in_use: AtomicBool::new(true),
+            next: ptr::null(),
+            guard_count: Cell::new(0),
+            pin_count: Cell::new(0),
+            has_handle: Cell::new(true),
+        }));
+        let mut head = self.records.load(Relaxed);
+        loop {
+            // Safety: nobody else sees the new record yet.
+            unsafe { (*new).next = head };
+            // Release: threads advancing the epoch must see the record initialised.
+            match self
+                .records
+                .compare_exchange_weak(head, new, Release, Relaxed)
+            {
+                Ok(_) => break,
+                Err(h) => head = h,
+            }
+        }
+        // Safety: the record lives as long as the collector.
+        unsafe { &*new }
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.epoch.load(SeqCst)
+    }
+
+    /// Destructions deferred but not done yet.
+    pub fn garbage_count(&self) -> usize {
+        self.garbage_count.load(Relaxed)
+    }
+
+    /// Advances the epoch if every pinned thread has seen the current one,
+    /// then destroys the garbage that's old enough. Returns how much that was.
+    /// Happens automatically every now and then when pinning.
+    pub fn collect(&self) -> usize {
+        // Take the list before reading the epoch, so every node in it is tagged with that epoch or an older one.
+        // (Reading the epoch first, another thread could advance it and defer something newer in between.)
+        let garbage = self.garbage.swap(ptr::null_mut(), Acquire);
+        let epoch = self.try_advance();
+        self.destroy_garbage(garbage, epoch)
+    }
+
+    /// Destroys what's at least two epochs older than `epoch` in the taken `garbage` list,
+    /// and puts the rest back.
+    fn destroy_garbage(&self, mut garbage: *mut Garbage, epoch: usize) -> usize {
+        let mut kept_first: *mut Garbage = ptr::null_mut();
+        let mut kept_last: *mut Garbage = ptr::null_mut();
+        let mut kept = 0;
+        let mut destroyed = 0;
+        while !garbage.is_null() {
+            // Safety: we took the list, it's ours alone.
+            let node = unsafe { &mut *garbage };
+            garbage = node.next;
+            // Signed, so a newer tag is never mistaken for an old one that wrapped around.
+            if epoch.wrapping_sub(node.epoch) as isize >= 2 {
+                // Safety: every thread that could have seen the object has unpinned since.
+                unsafe {
+                    let node = Box::from_raw(node);
+                    (node.destroy)(node.ptr);
+                }
+                destroyed += 1;
+            } else {
+                node.next = kept_first;
+                if kept_first.is_null() {
+                    kept_last = node;
+                }
+                kept_first = node;
+                kept += 1;
+            }
+        }
+        self.garbage_count.fetch_sub(kept + destroyed, Relaxed);
+        if kept > 0 {
+            self.push_garbage(kept_first, kept_last, kept);
+        }
+        destroyed
+    }
+
+    fn try_advance(&self) -> usize {
+        let epoch = self.epoch.load(SeqCst);
+        // SeqCst pairs with the fence in pin:
+        // either we see the thread pinned, or it sees the epoch we're about to leave (or a later one).
+        fence(SeqCst);
+        let mut record = self.records.load(Acquire);
+        while !record.is_null() {
+            // Safety: records are only freed along with the collector.
+            let r = unsafe { &*record };
+            let pinned = r.epoch.load(Relaxed);
+            if pinned & 1 == 1 && pinned >> 1 != epoch {
+                // Still pinned in an older epoch.
+                return epoch;
+            }
+            record = r.next as *mut Record;
+        }
+        // Acquire: whatever the threads did while pinned in older epochs happens before what we free.
+        fence(Acquire);
+        // Another thread might have advanced it in the meantime, don't move it twice.
+        match self
+            .epoch
+            .compare_exchange(epoch, epoch.wrapping_add(1), SeqCst, SeqCst)
+        {
+            Ok(_) => epoch.wrapping_add(1),
+            Err(current) => current,
+        }
+    }
+
+    fn defer(&self, ptr: *mut u8, destroy: unsafe fn(*mut u8)) {
+        // SeqCst: the tag must be at least the epoch of every thread that could have loaded the pointer
+        // before it was removed, see try_advance.
+        fence(SeqCst);
+        let garbage = Box::into_raw(Box::new(Garbage {
+            epoch: self.epoch.load(SeqCst),
+            ptr,
+            destroy,
+            next: ptr::null_mut(),
+        }));
+        self.push_garbage(garbage, garbage, 1);
+    }
+
+    /// Pushes the list from `first` to `last`, which has `count` nodes.
+    fn push_garbage(&self, first: *mut Garbage, last: *mut Garbage, count: usize) {
+        self.garbage_count.fetch_add(count, Relaxed);
+        let mut head = self.garbage.load(Relaxed);
+        loop {
+            // Safety: the list isn't visible to anyone else yet.
+            unsafe { (*last).next = head };
+            // Release: the collection that takes the list must see the nodes initialised.
+            match self
+                .garbage
+                .compare_exchange_weak(head, first, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // Nothing can be pinned anymore: handles and guards borrow the collector.
+        let mut garbage = *self.garbage.get_mut();
+        while !garbage.is_null() {
+            // Safety: everything in the list was deferred to be destroyed.
+            unsafe {
+                let node = Box::from_raw(garbage);
+                (node.destroy)(node.ptr);
+                garbage = node.next;
+            }
+        }
+        let mut record = *self.records.get_mut();
+        while !record.is_null() {
+            // Safety: nothing uses the records anymore.
+            let r = unsafe { Box::from_raw(record) };
+            record = r.next as *mut Record;
+        }
+    }
+}
+
+impl<'c> LocalHandle<'c> {
+    pub fn pin(&self) -> Guard<'c> {
+        let record = self.record;
+        let guard_count = record.guard_count.get();
+        record.guard_count.set(guard_count + 1);
+        if guard_count == 0 {
+            let epoch = self.collector.epoch.load(SeqCst);
+            record.epoch.store(epoch << 1 | 1, Relaxed);
+            // SeqCst pairs with the fence in try_advance.
+            // Everything we load from now on is at least as new as what that thread saw.
+            fence(SeqCst);
+
+            let pin_count = record.pin_count.get().wrapping_add(1);
+            record.pin_count.set(pin_count);
+            if pin_count.is_multiple_of(PINS_BETWEEN_COLLECT) {
+                self.collector.collect();
+            }
+        }
+        Guard {
+            collector: self.collector,
+            record,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.record.guard_count.get() > 0
+    }
+
+    pub fn collector(&self) -> &'c Collector {
+        self.collector
+    }
+}
+
+impl Drop for LocalHandle<'_> {
+    fn drop(&mut self) {
+        self.record.has_handle.set(false);
+        // Otherwise the last guard releases it.
+        if self.record.guard_count.get() == 0 {
+            self.record.in_use.store(false, Release);
+        }
+    }
+}
+
+impl<'c> Guard<'c> {
+    /// Drops the box behind `ptr` once no pinned thread can have a pointer to it anymore.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from an `Owned` (a box), already be unreachable for threads that pin after this,
+    /// and only be destroyed once. The drop might run on any thread.
+    pub unsafe fn defer_destroy<T>(&self, ptr: Shared<'_, T>) {
+        self.collector.defer(ptr.as_raw() as *mut u8, destroy::<T>);
+    }
+
+    pub fn collector(&self) -> &'c Collector {
+        self.collector
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        let record = self.record;
+        let guard_count = record.guard_count.get() - 1;
+        record.guard_count.set(guard_count);
+        if guard_count == 0 {
+            // Release: we're done with everything we loaded before threads see us unpinned.
+            record.epoch.store(0, Release);
+            if !record.has_handle.get() {
+                record.in_use.store(false, Release);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::atomic::Owned;
+
+    #[test]
+    fn test_destroyed_after_guards_are_dropped() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let collector = Collector::new();
+        let a = collector.register();
+        // stands in for another thread that's pinned for a long time
+        let b = collector.register();
+
+        let b_guard = b.pin();
+        let a_guard = a.pin();
+        let object = Owned::new(DetectDrop).into_shared(&a_guard);
+        unsafe { a_guard.defer_destroy(object) };
+        drop(a_guard);
+        assert_eq!(collector.garbage_count(), 1);
+
+        // b is in the current epoch, so it can advance once, but not again
+        let epoch = collector.epoch();
+        for _ in 0..3 {
+            assert_eq!(collector.collect(), 0);
+        }
+        assert_eq!(collector.epoch(), epoch + 1);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+
+        // the object is tagged with the epoch before that, so advancing once more is enough
+        drop(b_guard);
+        assert_eq!(collector.collect(), 1);
+        assert_eq!(collector.epoch(), epoch + 2);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+        assert_eq!(collector.garbage_count(), 0);
+    }
+
+    #[test]
+    fn test_newer_garbage_is_kept() {
+        let collector = Collector::new();
+        // nothing's pinned, so every collection advances the epoch
+        for _ in 0..5 {
+            collector.collect();
+        }
+        let epoch = collector.epoch();
+        let handle = collector.register();
+        let guard = handle.pin();
+        unsafe { guard.defer_destroy(Owned::new(0u64).into_shared(&guard)) };
+
+        // a collection that read the epoch before this was deferred
+        let garbage = collector.garbage.swap(ptr::null_mut(), Acquire);
+        assert_eq!(collector.destroy_garbage(garbage, epoch - 1), 0);
+        assert_eq!(collector.garbage_count(), 1);
+        drop(guard);
+        collector.collect();
+        assert_eq!(collector.collect(), 1);
+    }
+
+    #[test]
+    fn test_nested_guards() {
+        let collector = Collector::new();
+        let handle = collector.register();
+        let outer = handle.pin();
+        let inner = handle.pin();
+        drop(outer);
+        assert!(handle.is_pinned());
+        drop(inner);
+        assert!(!handle.is_pinned());
+    }
+
+    #[test]
+    fn test_records_are_reused() {
+        let collector = Collector::new();
+        let a = collector.register();
+        let record: *const Record = a.record;
+        let guard = a.pin();
+        // a is pinned, so dropping the handle doesn't release its record, the guard does
+        drop(a);
+        let b = collector.register();
+        assert!(!ptr::eq(b.record, record));
+        drop(guard);
+        let c = collector.register();
+        assert!(ptr::eq(c.record, record));
+        drop(b);
+    }
+
+    #[test]
+    fn test_unpinned_collector_is_destroyed() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop;
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let collector = Collector::new();
+        let handle = collector.register();
+        let guard = handle.pin();
+        unsafe { guard.defer_destroy(Owned::new(DetectDrop).into_shared(&guard)) };
+        drop(guard);
+        drop(handle);
+        // whatever is left is destroyed along with the collector
+        drop(collector);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    }
+}
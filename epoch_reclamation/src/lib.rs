@@ -0,0 +1,3 @@
+pub mod atomic;
+pub mod collector;
+pub mod queue;
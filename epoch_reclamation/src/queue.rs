@@ -0,0 +1,263 @@
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use crate::atomic::{Atomic, Owned, Shared};
+use crate::collector::{Collector, Guard};
+
+// A lock-free FIFO queue (Michael and Scott, "Simple, fast, and practical non-blocking and blocking
+// concurrent queue algorithms").
+//
+// A linked list that always starts with a sentinel node, whose data has already been taken (or never existed).
+// Pushing links a node after the last one, then moves `tail` to it.
+// `tail` can lag one node behind, any thread that notices helps moving it.
+// Popping moves `head` to the next node, which becomes the new sentinel, and takes its data.
+//
+// Other threads might still be reading the old sentinel's `next`, so it's destroyed through the collector.
+// That also prevents the ABA problem: a node can't be reused while a pinned thread might still have it.
+pub struct Queue<'c, T> {
+    head: Atomic<Node<T>>,
+    tail: Atomic<Node<T>>,
+    collector: &'c Collector,
+}
+
+struct Node<T> {
+    // taken by pop, or uninitialised in the first sentinel
+    data: MaybeUninit<T>,
+    next: Atomic<Node<T>>,
+}
+
+unsafe impl<T: Send> Send for Queue<'_, T> {}
+unsafe impl<T: Send> Sync for Queue<'_, T> {}
+
+impl<T> Default for Queue<'static, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Queue<'static, T> {
+    /// A queue using the global collector.
+    pub fn new() -> Self {
+        Self::new_in(crate::collector::default_collector())
+    }
+}
+
+impl<'c, T> Queue<'c, T> {
+    pub fn new_in(collector: &'c Collector) -> Self {
+        let mut head = Atomic::from(Owned::new(Node {
+            data: MaybeUninit::uninit(),
+            next: Atomic::null(),
+        }));
+        // Nobody else can see the sentinel yet, so there's no need to pin.
+        let tail = Atomic::null();
+        tail.store(Shared::from_raw(head.get_mut()), Relaxed);
+        Self {
+            head,
+            tail,
+            collector,
+        }
+    }
+
+    /// Pushes with a guard pinned on this queue's collector, like one from a `LocalHandle`
+    /// the thread keeps around, instead of registering for every operation.
+    pub fn push_pinned(&self, data: T, guard: &Guard) {
+        self.check_guard(guard);
+        let node = Owned::new(Node {
+            data: MaybeUninit::new(data),
+            next: Atomic::null(),
+        })
+        .into_shared(guard);
+        loop {
+            let tail = self.tail.load(Acquire, guard);
+            // Safety: nodes are only destroyed through the collector, and we're pinned.
+            let next = unsafe { tail.deref() }.next.load(Acquire, guard);
+            if !next.is_null() {
+                // `tail` is behind, help moving it and try again.
+                let _ = self
+                    .tail
+                    .compare_exchange(tail, next, Release, Relaxed, guard);
+                continue;
+            }
+            // Release: whoever pops the node must see it initialised.
+            if unsafe { tail.deref() }
+                .next
+                .compare_exchange(Shared::null(), node, Release, Relaxed, guard)
+                .is_ok()
+            {
+                // If this fails, another thread already helped.
+                let _ = self
+                    .tail
+                    .compare_exchange(tail, node, Release, Relaxed, guard);
+                return;
+            }
+        }
+    }
+
+    /// Like `push_pinned`.
+    pub fn pop_pinned(&self, guard: &Guard) -> Option<T> {
+        self.check_guard(guard);
+        loop {
+            let head = self.head.load(Acquire, guard);
+            // Safety: nodes are only destroyed through the collector, and we're pinned.
+            let next = unsafe { head.deref() }.next.load(Acquire, guard);
+            let next_node = unsafe { next.as_ref() }?;
+            // Don't let `head` overtake `tail`, `tail` would point to a destroyed node.
+            let tail = self.tail.load(Relaxed, guard);
+            if tail == head {
+                let _ = self
+                    .tail
+                    .compare_exchange(tail, next, Release, Relaxed, guard);
+            }
+            if self
+                .head
+                .compare_exchange(head, next, Acquire, Relaxed, guard)
+                .is_ok()
+            {
+                // Safety: we moved `head` past `head`, so only we take the data from the new sentinel,
+                // and only we destroy the old one. Nobody that pins from now on can reach it.
+                unsafe {
+                    let data = next_node.data.assume_init_read();
+                    guard.defer_destroy(head);
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    /// Like `push_pinned`.
+    pub fn is_empty_pinned(&self, guard: &Guard) -> bool {
+        self.check_guard(guard);
+        let head = self.head.load(Acquire, guard);
+        // Safety: like in pop.
+        unsafe { head.deref() }.next.load(Acquire, guard).is_null()
+    }
+
+    // Being pinned on another collector doesn't stop this one from destroying nodes.
+    fn check_guard(&self, guard: &Guard) {
+        assert!(
+            ptr::eq(guard.collector(), self.collector),
+            "the guard is pinned on another collector"
+        );
+    }
+}
+
+// Without a guard, the thread is pinned with the global collector's handle for this thread.
+// (Panics for a queue in another collector that happens to be 'static, use the _pinned versions for that.)
+impl<T> Queue<'static, T> {
+    pub fn push(&self, data: T) {
+        self.push_pinned(data, &crate::collector::pin());
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.pop_pinned(&crate::collector::pin())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.is_empty_pinned(&crate::collector::pin())
+    }
+}
+
+impl<T> Drop for Queue<'_, T> {
+    fn drop(&mut self) {
+        // Nobody else can access the queue anymore, so there's no need to pin: free the nodes right away.
+        // Nodes popped earlier aren't in the list anymore, they're up to the collector.
+        let mut node = self.head.get_mut();
+        let mut sentinel = true;
+        while !node.is_null() {
+            // Safety: every node in the list is still alive, and they all came from an Owned box.
+            // Only the first one is a sentinel whose data was taken (or never there).
+            let mut owned = unsafe { Box::from_raw(node) };
+            if !sentinel {
+                unsafe { owned.data.assume_init_drop() };
+            }
+            sentinel = false;
+            node = owned.next.get_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn test_fifo() {
+        let queue = Queue::new();
+        assert!(queue.is_empty());
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.pop(), Some(1));
+        queue.push(3);
+        assert!(!queue.is_empty());
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    #[should_panic = "another collector"]
+    fn test_guard_from_another_collector() {
+        let collector = Collector::new();
+        let queue = Queue::new_in(&collector);
+        queue.push_pinned(1, &crate::collector::pin());
+    }
+
+    #[test]
+    fn test_stress() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        const THREADS: usize = 4;
+        const ITEMS: usize = 10_000;
+
+        let collector = Collector::new();
+        let queue = Queue::new_in(&collector);
+        let popped: usize = thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let (queue, collector) = (&queue, &collector);
+                    s.spawn(move || {
+                        // registered once, pinned for each operation
+                        let handle = collector.register();
+                        let mut popped = 0;
+                        // items pushed by each thread come out in order
+                        let mut last = [None; THREADS];
+                        for i in 0..ITEMS {
+                            queue.push_pinned(DetectDrop(t * ITEMS + i), &handle.pin());
+                            if i % 2 == 0 {
+                                if let Some(item) = queue.pop_pinned(&handle.pin()) {
+                                    let (from, i) = (item.0 / ITEMS, item.0 % ITEMS);
+                                    assert!(last[from].is_none_or(|last| last < i));
+                                    last[from] = Some(i);
+                                    popped += 1;
+                                }
+                            }
+                        }
+                        popped
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).sum()
+        });
+        // popped items were dropped right away, the rest are still in the queue
+        assert_eq!(NUM_DROPS.load(Relaxed), popped);
+        drop(queue);
+        assert_eq!(NUM_DROPS.load(Relaxed), THREADS * ITEMS);
+        // nothing's pinned anymore, so every popped node can be destroyed:
+        // one collection to get everything into an old enough epoch, one more to destroy it
+        collector.collect();
+        collector.collect();
+        assert_eq!(collector.garbage_count(), 0);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
+
+// Hazard pointers (Maged Michael, "Hazard Pointers: Safe Memory Reclamation for Lock-Free Objects").
+//
+// A thread about to dereference a pointer it loaded from shared memory first publishes it in a hazard slot,
+// then checks that the pointer is still there. If it is, whoever removes it later will see the slot.
+// Removed objects aren't freed right away, but retired: once there are enough of them,
+// the retired list is scanned, and everything that isn't in any hazard slot is freed.
+//
+// Slots and retired objects belong to a domain. Most code uses the global one,
+// separate domains keep unrelated structures from scanning each other's slots.
+// Slots live in a lock-free linked list that only grows: a released slot is reused, not freed.
+// The retired objects are in a lock-free linked list too. A scan takes the whole list,
+// and puts back whatever is still protected.
+
+/// Scan once at least this many objects are retired,
+/// or twice the number of hazard slots if that's more, so a scan frees at least half of them.
+const SCAN_THRESHOLD: usize = 64;
+
+pub struct Domain {
+    slots: AtomicPtr<Slot>,
+    slot_count: AtomicUsize,
+    retired: AtomicPtr<Retired>,
+    retired_count: AtomicUsize,
+}
+
+struct Slot {
+    protected: AtomicPtr<u8>,
+    in_use: AtomicBool,
+    next: *const Slot, // never changes after the slot is in the list
+}
+
+/// A retired object, with its deleter and the type it was retired as erased.
+struct Retired {
+    ptr: *mut u8,
+    deleter: *const (), // the `unsafe fn(*mut T)` given to `retire`
+    delete: unsafe fn(*mut u8, *const ()), // calls `deleter` with the right types
+    next: *mut Retired,
+}
+
+unsafe fn delete<T>(ptr: *mut u8, deleter: *const ()) {
+    // Safety: `retire::<T>` turned an `unsafe fn(*mut T)` into `deleter`.
+    let deleter: unsafe fn(*mut T) = std::mem::transmute(deleter);
+    deleter(ptr.cast::<T>());
+}
+
+// The raw pointers are only used as described above.
+unsafe impl Send for Domain {}
+unsafe impl Sync for Domain {}
+
+static GLOBAL: Domain = Domain::new();
+
+/// Protects one pointer at a time from being freed.
+/// Holds on to a slot of its domain until it's dropped.
+pub struct HazardPointer<'d> {
+    slot: &'d Slot,
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Domain {
+    pub const fn new() -> Self {
+        Self {
+            slots: AtomicPtr::new(ptr::null_mut()),
+            slot_count: AtomicUsize::new(0),
+            retired: AtomicPtr::new(ptr::null_mut()),
+            retired_count: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn global() -> &'static Domain {
+        &GLOBAL
+    }
+
+    /// Reuses a released slot, or adds one.
+    fn acquire_slot(&self) -> &Slot {
+        let mut slot = self.slots.load(Acquire);
+        while !slot.is_null() {
+            // Safety: slots are only freed along with the domain.
+            let s = unsafe { &*slot };
+            if !s.in_use.load(Relaxed)
+                && s.in_use
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+            {
+                return s;
+            }
+            slot = s.next as *mut Slot;
+        }
+        let new = Box::into_raw(Box::new(Slot {
+            protected: AtomicPtr::new(ptr::null_mut()),
+            in_use: AtomicBool::new(true),
+            next: ptr::null(),
+        }));
+        let mut head = self.slots.load(Relaxed);
+        loop {
+            // Safety: nobody else sees the new slot yet.
+            unsafe { (*new).next = head };
+            // Release: scans that see the slot must see it initialised.
+            match self
+                .slots
+                .compare_exchange_weak(head, new, Release, Relaxed)
+            {
+                Ok(_) => break,
+                Err(h) => head = h,
+            }
+        }
+        self.slot_count.fetch_add(1, Relaxed);
+        // Safety: the slot lives as long as the domain.
+        unsafe { &*new }
+    }
+
+    /// Hands `ptr` over to be freed with `deleter` once no hazard pointer protects it anymore.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must already be unreachable for threads that didn't protect it yet,
+    /// it must only be retired once, and `deleter` must be fine to call on it from any thread.
+    pub unsafe fn retire<T>(&self, ptr: *mut T, deleter: unsafe fn(*mut T)) {
+        let retired = Box::into_raw(Box::new(Retired {
+            ptr: ptr.cast::<u8>(),
+            deleter: deleter as *const (),
+            delete: delete::<T>,
+            next: ptr::null_mut(),
+        }));
+        self.push_retired(retired, retired, 1);
+        let threshold = SCAN_THRESHOLD.max(2 * self.slot_count.load(Relaxed));
+        if self.retired_count.load(Relaxed) >= threshold {
+            self.reclaim();
+        }
+    }
+
+    /// Pushes the list from `first` to `last`, which has `count` objects.
+    fn push_retired(&self, first: *mut Retired, last: *mut Retired, count: usize) {
+        self.retired_count.fetch_add(count, Relaxed);
+        let mut head = self.retired.load(Relaxed);
+        loop {
+            // Safety: the list isn't visible to anyone else yet.
+            unsafe { (*last).next = head };
+            // Release: the scan that takes the list must see the nodes initialised.
+            match self
+                .retired
+                .compare_exchange_weak(head, first, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    /// Frees every retired object that isn't protected, returns how many that were.
+    /// Happens automatically once enough objects are retired.
+    pub fn reclaim(&self) -> usize {
+        let mut retired = self.retired.swap(ptr::null_mut(), Acquire);
+        if retired.is_null() {
+            return 0;
+        }
+        // The objects were made unreachable before being retired.
+        // SeqCst pairs with the fence in protect:
+        // either we see the hazard, or the protecting thread sees the object is gone.
+        fence(SeqCst);
+        let protected = self.protected();
+
+        let mut kept_first: *mut Retired = ptr::null_mut();
+        let mut kept_last: *mut Retired = ptr::null_mut();
+        let mut kept = 0;
+        let mut freed = 0;
+        while !retired.is_null() {
+            // Safety: we took the list, it's ours alone.
+            let node = unsafe { &mut *retired };
+            retired = node.next;
+            if protected.contains(&node.ptr) {
+                node.next = kept_first;
+                if kept_first.is_null() {
+                    kept_last = node;
+                }
+                kept_first = node;
+                kept += 1;
+            } else {
+                // Safety: the object is unreachable and nobody protects it, it was retired for this.
+                unsafe {
+                    let node = Box::from_raw(node);
+                    (node.delete)(node.ptr, node.deleter);
+                }
+                freed += 1;
+            }
+        }
+        self.retired_count.fetch_sub(kept + freed, Relaxed);
+        if kept > 0 {
+            self.push_retired(kept_first, kept_last, kept);
+        }
+        freed
+    }
+
+    /// Objects retired but not freed yet.
+    pub fn retired_count(&self) -> usize {
+        self.retired_count.load(Relaxed)
+    }
+
+    fn protected(&self) -> HashSet<*mut u8> {
+        let mut protected = HashSet::new();
+        let mut slot = self.slots.load(Acquire);
+        while !slot.is_null() {
+            // Safety: slots are only freed along with the domain.
+            let s = unsafe { &*slot };
+            let p = s.protected.load(SeqCst);
+            if !p.is_null() {
+                protected.insert(p);
+            }
+            slot = s.next as *mut Slot;
+        }
+        protected
+    }
+}
+
+impl Drop for Domain {
+    fn drop(&mut self) {
+        // Nothing can be protected anymore: hazard pointers borrow the domain.
+        let mut retired = *self.retired.get_mut();
+        while !retired.is_null() {
+            // Safety: everything in the list was retired to be freed.
+            unsafe {
+                let node = Box::from_raw(retired);
+                (node.delete)(node.ptr, node.deleter);
+                retired = node.next;
+            }
+        }
+        let mut slot = *self.slots.get_mut();
+        while !slot.is_null() {
+            // Safety: nothing uses the slots anymore.
+            let s = unsafe { Box::from_raw(slot) };
+            slot = s.next as *mut Slot;
+        }
+    }
+}
+
+impl HazardPointer<'static> {
+    /// A hazard pointer in the global domain.
+    pub fn new() -> Self {
+        Self::new_in(Domain::global())
+    }
+}
+
+impl Default for HazardPointer<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'d> HazardPointer<'d> {
+    pub fn new_in(domain: &'d Domain) -> Self {
+        Self {
+            slot: domain.acquire_slot(),
+        }
+    }
+
+    /// Loads the pointer in `src` and protects it, replacing whatever was protected before.
+    /// The object it points to isn't freed until this is reset, protects something else or is dropped,
+    /// as long as objects removed from `src` are retired in the same domain.
+    pub fn protect<T>(&mut self, src: &AtomicPtr<T>) -> *mut T {
+        let mut ptr = src.load(Relaxed);
+        loop {
+            self.slot.protected.store(ptr.cast::<u8>(), Relaxed);
+            // SeqCst pairs with the fence in reclaim, see there.
+            fence(SeqCst);
+            // Acquire: the object must be visible before we use it.
+            let current = src.load(Acquire);
+            if current == ptr {
+                return ptr;
+            }
+            // It was removed (and maybe retired) before the hazard became visible, try again.
+            ptr = current;
+        }
+    }
+
+    /// Stops protecting anything.
+    pub fn reset(&mut self) {
+        // Release: we're done with the object before it can be freed.
+        self.slot.protected.store(ptr::null_mut(), Release);
+    }
+}
+
+impl Drop for HazardPointer<'_> {
+    fn drop(&mut self) {
+        self.reset();
+        self.slot.in_use.store(false, Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    unsafe fn drop_box<T>(ptr: *mut T) {
+        drop(Box::from_raw(ptr));
+    }
+
+    #[test]
+    fn test_protected_until_reset() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        // not zero sized: every box of a zero sized type is at the same (dangling) address
+        struct DetectDrop(#[allow(dead_code)] u64);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let domain = Domain::new();
+        let shared = AtomicPtr::new(Box::into_raw(Box::new(DetectDrop(0))));
+
+        let mut hazard = HazardPointer::new_in(&domain);
+        let protected = hazard.protect(&shared);
+        // removed and retired while protected
+        let old = shared.swap(Box::into_raw(Box::new(DetectDrop(0))), Relaxed);
+        assert_eq!(old, protected);
+        unsafe { domain.retire(old, drop_box) };
+        assert_eq!(domain.reclaim(), 0);
+        assert_eq!(NUM_DROPS.load(Relaxed), 0);
+
+        // protecting something else releases it too
+        hazard.protect(&shared);
+        assert_eq!(domain.reclaim(), 1);
+        assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+        let old = shared.swap(ptr::null_mut(), Relaxed);
+        unsafe { domain.retire(old, drop_box) };
+        assert_eq!(domain.reclaim(), 0);
+        hazard.reset();
+        assert_eq!(domain.reclaim(), 1);
+        assert_eq!(NUM_DROPS.load(Relaxed), 2);
+    }
+
+    #[test]
+    fn test_batched_scan() {
+        let domain = Domain::new();
+        static FREED: AtomicUsize = AtomicUsize::new(0);
+        unsafe fn count_and_drop(ptr: *mut u64) {
+            FREED.fetch_add(1, Relaxed);
+            drop(Box::from_raw(ptr));
+        }
+
+        for i in 0..SCAN_THRESHOLD - 1 {
+            unsafe { domain.retire(Box::into_raw(Box::new(i as u64)), count_and_drop) };
+        }
+        // not enough to scan yet
+        assert_eq!(FREED.load(Relaxed), 0);
+        unsafe { domain.retire(Box::into_raw(Box::new(0)), count_and_drop) };
+        assert_eq!(FREED.load(Relaxed), SCAN_THRESHOLD);
+        assert_eq!(domain.retired_count(), 0);
+
+        // whatever is left is freed along with the domain
+        unsafe { domain.retire(Box::into_raw(Box::new(0)), count_and_drop) };
+        drop(domain);
+        assert_eq!(FREED.load(Relaxed), SCAN_THRESHOLD + 1);
+    }
+
+    #[test]
+    fn test_slots_are_reused() {
+        let domain = Domain::new();
+        let a = HazardPointer::new_in(&domain);
+        let b = HazardPointer::new_in(&domain);
+        assert_eq!(domain.slot_count.load(Relaxed), 2);
+        drop(a);
+        let _c = HazardPointer::new_in(&domain);
+        assert_eq!(domain.slot_count.load(Relaxed), 2);
+        drop(b);
+    }
+}
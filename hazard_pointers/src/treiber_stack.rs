@@ -0,0 +1,181 @@
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use crate::domain::{Domain, HazardPointer};
+
+// A lock-free stack: a linked list whose head is swapped with compare-and-swap.
+//
+// Popping reads `head.next` before swapping `head` out, and another thread might pop and free that head
+// in the meantime. A hazard pointer on the head keeps it around until we're done with it.
+// It also prevents the ABA problem: a protected node can't be freed, so it can't be reused as
+// a new node that happens to be at the same address while we're looking at the old one.
+pub struct TreiberStack<'d, T> {
+    head: AtomicPtr<Node<T>>,
+    domain: &'d Domain,
+}
+
+struct Node<T> {
+    // moved out by pop, the node is freed later
+    data: ManuallyDrop<T>,
+    next: *mut Node<T>,
+}
+
+unsafe impl<T: Send> Send for TreiberStack<'_, T> {}
+unsafe impl<T: Send> Sync for TreiberStack<'_, T> {}
+
+impl<T> Default for TreiberStack<'static, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TreiberStack<'static, T> {
+    /// A stack using the global domain.
+    pub fn new() -> Self {
+        Self::new_in(Domain::global())
+    }
+}
+
+impl<'d, T> TreiberStack<'d, T> {
+    pub fn new_in(domain: &'d Domain) -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            domain,
+        }
+    }
+
+    pub fn push(&self, data: T) {
+        let node = Box::into_raw(Box::new(Node {
+            data: ManuallyDrop::new(data),
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Relaxed);
+        loop {
+            // Safety: the node isn't visible to anyone else yet.
+            unsafe { (*node).next = head };
+            // Release: whoever pops the node must see it initialised.
+            match self
+                .head
+                .compare_exchange_weak(head, node, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut hazard = HazardPointer::new_in(self.domain);
+        loop {
+            let head = hazard.protect(&self.head);
+            if head.is_null() {
+                return None;
+            }
+            // Safety: protected, so it's not freed even if another thread pops it.
+            let next = unsafe { (*head).next };
+            if self
+                .head
+                .compare_exchange(head, next, Acquire, Relaxed)
+                .is_ok()
+            {
+                // Safety: we popped it, so only we take the data.
+                // Other threads might still be reading `next`, so the node itself has to be retired.
+                unsafe {
+                    let data = ManuallyDrop::take(&mut (*head).data);
+                    self.domain.retire(head, drop_node);
+                    return Some(data);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Relaxed).is_null()
+    }
+}
+
+/// Frees a popped node, without dropping the data that was moved out of it.
+unsafe fn drop_node<T>(node: *mut Node<T>) {
+    drop(Box::from_raw(node));
+}
+
+impl<T> Drop for TreiberStack<'_, T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            // Safety: nobody else can access the stack anymore, and these nodes were never popped.
+            let mut n = unsafe { Box::from_raw(node) };
+            unsafe { ManuallyDrop::drop(&mut n.data) };
+            node = n.next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn test_lifo() {
+        let stack = TreiberStack::new();
+        assert!(stack.is_empty());
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(3);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn test_stress() {
+        static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct DetectDrop(usize);
+
+        impl Drop for DetectDrop {
+            fn drop(&mut self) {
+                NUM_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        const THREADS: usize = 4;
+        const ITEMS: usize = 10_000;
+
+        let domain = Domain::new();
+        let stack = TreiberStack::new_in(&domain);
+        let popped: usize = thread::scope(|s| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let stack = &stack;
+                    s.spawn(move || {
+                        let mut popped = 0;
+                        for i in 0..ITEMS {
+                            stack.push(DetectDrop(t * ITEMS + i));
+                            if i % 2 == 0 {
+                                if let Some(item) = stack.pop() {
+                                    assert!(item.0 < THREADS * ITEMS);
+                                    popped += 1;
+                                }
+                            }
+                        }
+                        popped
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).sum()
+        });
+        // popped items were dropped right away, the rest are still in the stack
+        assert_eq!(NUM_DROPS.load(Relaxed), popped);
+        drop(stack);
+        assert_eq!(NUM_DROPS.load(Relaxed), THREADS * ITEMS);
+        // nothing's protected anymore, so every popped node can be freed
+        domain.reclaim();
+        assert_eq!(domain.retired_count(), 0);
+    }
+}